@@ -0,0 +1,384 @@
+//! Derive macros for `cconst`
+//!
+//! This crate is not meant to be used directly, enable the `derive` feature of
+//! `cconst` instead.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use syn::{Data, DeriveInput, Error, Fields, Index, LitStr};
+
+/// Derives `ConstBytes` for a struct
+///
+/// The struct must be `#[repr(C)]` or `#[repr(transparent)]` and every field
+/// must implement `ConstBytes`. Padding between fields is recorded in the
+/// generated layout and emitted as zeros.
+///
+/// The fingerprint covers the name and version of the defining crate, the
+/// path of the type and the names, offsets and fingerprints of all fields.
+///
+/// ```
+/// #[macro_use]
+/// extern crate cconst;
+///
+/// use cconst::ConstBytes;
+///
+/// #[derive(Copy, Clone, ConstBytes)]
+/// #[repr(C)]
+/// struct Entry {
+///     id: u8,
+///     offset: u32,
+/// }
+///
+/// # fn main() {
+/// let layout = Entry::layout();
+/// assert_eq!(layout.data_mask(),
+///            [true, false, false, false, true, true, true, true]);
+/// # }
+/// ```
+///
+/// Types without a defined layout are rejected:
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate cconst;
+///
+/// #[derive(Copy, Clone, ConstBytes)]
+/// struct Entry {
+///     id: u8,
+///     offset: u32,
+/// }
+/// # fn main() {}
+/// ```
+#[proc_macro_derive(ConstBytes)]
+pub fn derive_const_bytes(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+
+    match expand_const_bytes(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_const_bytes(input: &DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => {
+            return Err(Error::new(Span::call_site(),
+                                  "`ConstBytes` can only be derived for structs"))
+        }
+    };
+
+    if !has_defined_layout(input)? {
+        return Err(Error::new(Span::call_site(),
+                              "`ConstBytes` requires `#[repr(C)]` or `#[repr(transparent)]`"));
+    }
+
+    let members: Vec<_> = match *fields {
+        Fields::Named(ref named) => {
+            named.named.iter().map(|f| {
+                let ident = f.ident.as_ref().unwrap();
+                quote!(#ident)
+            }).collect()
+        }
+        Fields::Unnamed(ref unnamed) => {
+            (0..unnamed.unnamed.len()).map(|i| {
+                let index = Index::from(i);
+                quote!(#index)
+            }).collect()
+        }
+        Fields::Unit => Vec::new(),
+    };
+    let field_types: Vec<_> = fields.iter().map(|f| &f.ty).collect();
+
+    let name = &input.ident;
+    let mut generics = input.generics.clone();
+    {
+        let where_clause = generics.make_where_clause();
+        for ty in &field_types {
+            where_clause.predicates.push(syn::parse_quote!(#ty: ::cconst::ConstBytes));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let member_names: Vec<_> = members.iter().map(|m| m.to_string()).collect();
+
+    Ok(quote! {
+        unsafe impl #impl_generics ::cconst::ConstBytes for #name #ty_generics #where_clause {
+            const FINGERPRINT: u64 = {
+                let fp = ::cconst::fingerprint::of_str(
+                    match ::std::option_env!("CARGO_PKG_NAME") { Some(s) => s, None => "" });
+                let fp = ::cconst::fingerprint::combine(fp, ::cconst::fingerprint::of_str(
+                    match ::std::option_env!("CARGO_PKG_VERSION") { Some(s) => s, None => "" }));
+                let fp = ::cconst::fingerprint::combine(fp, ::cconst::fingerprint::of_str(
+                    ::std::concat!(::std::module_path!(), "::", ::std::stringify!(#name))));
+                #(
+                    let fp = ::cconst::fingerprint::combine(
+                        fp, ::cconst::fingerprint::of_str(#member_names));
+                    let fp = ::cconst::fingerprint::combine(
+                        fp, ::std::mem::offset_of!(Self, #members) as u64);
+                    let fp = ::cconst::fingerprint::combine(
+                        fp, <#field_types as ::cconst::ConstBytes>::FINGERPRINT);
+                )*
+                ::cconst::fingerprint::combine(fp, ::std::mem::size_of::<Self>() as u64)
+            };
+
+            fn layout() -> ::cconst::Layout {
+                let mut layout = ::cconst::Layout::new(::std::mem::size_of::<Self>());
+                #(
+                    layout.nested(::std::mem::offset_of!(Self, #members),
+                                  &<#field_types as ::cconst::ConstBytes>::layout());
+                )*
+                layout
+            }
+        }
+    })
+}
+
+/// Derives `ToConstExpr` for a struct or enum
+///
+/// The generated expression is a struct literal (or enum variant) naming the
+/// type by its identifier, which must be in scope at the `include!` site. A
+/// different path can be set using `#[const_expr(path = "...")]`.
+///
+/// Data behind a `&'static` field is inlined by default. With
+/// `#[const_expr(static = "...")]`, it is stored in a separate `static` item
+/// of the given type instead, see `ExprWriter::write_static`.
+///
+/// ```
+/// #[macro_use]
+/// extern crate cconst;
+///
+/// use cconst::ExprWriter;
+///
+/// #[derive(ToConstExpr)]
+/// #[const_expr(path = "::mycrate::Color")]
+/// enum Color {
+///     Named(&'static str),
+///     Rgb { r: u8, g: u8, b: u8 },
+/// }
+///
+/// # fn main() {
+/// assert_eq!(ExprWriter::render(&Color::Rgb { r: 1, g: 2, b: 3 }),
+///            "::mycrate::Color::Rgb { r: 1u8, g: 2u8, b: 3u8, }");
+/// assert_eq!(ExprWriter::render(&Color::Named("red")),
+///            "::mycrate::Color::Named(&*\"red\", )");
+/// # }
+/// ```
+///
+/// Referenced data stored in statics:
+///
+/// ```
+/// #[macro_use]
+/// extern crate cconst;
+///
+/// use cconst::ExprWriter;
+///
+/// #[derive(ToConstExpr)]
+/// struct Table {
+///     #[const_expr(static = "[u16]")]
+///     ids: &'static [u16],
+/// }
+///
+/// # fn main() {
+/// assert_eq!(ExprWriter::render(&Table { ids: &[7, 9] }),
+///            "{
+///     static __CCONST_STATIC_0: &'static [u16] = &[7u16, 9u16, ];
+///     Table { ids: __CCONST_STATIC_0, }
+/// }");
+/// # }
+/// ```
+#[proc_macro_derive(ToConstExpr, attributes(const_expr))]
+pub fn derive_to_const_expr(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+
+    match expand_to_const_expr(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_to_const_expr(input: &DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let name = &input.ident;
+    let path = match expr_path(input)? {
+        Some(path) => path,
+        None => name.to_string(),
+    };
+
+    let mut field_types = Vec::new();
+    let arms: Vec<_> = match input.data {
+        Data::Struct(ref data) => {
+            field_types.extend(data.fields.iter().map(|f| &f.ty));
+            vec![render_fields(quote!(#name), &path, &data.fields)?]
+        }
+        Data::Enum(ref data) => {
+            data.variants
+                .iter()
+                .map(|v| {
+                    let ident = &v.ident;
+                    field_types.extend(v.fields.iter().map(|f| &f.ty));
+                    render_fields(quote!(#name::#ident), &format!("{}::{}", path, ident), &v.fields)
+                })
+                .collect::<Result<_, _>>()?
+        }
+        Data::Union(_) => {
+            return Err(Error::new(Span::call_site(),
+                                  "`ToConstExpr` cannot be derived for unions"))
+        }
+    };
+
+    let mut generics = input.generics.clone();
+    {
+        let where_clause = generics.make_where_clause();
+        // bounding only generic fields keeps recursive types such as trees
+        // from overflowing trait resolution
+        for ty in field_types.iter().filter(|ty| mentions_type_param(ty, &input.generics)) {
+            where_clause.predicates.push(syn::parse_quote!(#ty: ::cconst::ToConstExpr));
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::cconst::ToConstExpr for #name #ty_generics #where_clause {
+            fn to_const_expr(&self, out: &mut ::cconst::ExprWriter) -> ::std::fmt::Result {
+                match *self {
+                    #(#arms)*
+                }
+            }
+        }
+    })
+}
+
+/// Creates a match arm writing a literal for `fields` constructed through
+/// `path`.
+fn render_fields(pattern: proc_macro2::TokenStream,
+                 path: &str,
+                 fields: &Fields)
+                 -> Result<proc_macro2::TokenStream, Error> {
+    let bindings: Vec<_> = (0..fields.len())
+        .map(|i| format_ident!("__field{}", i))
+        .collect();
+
+    let mut writes = Vec::new();
+    for (field, binding) in fields.iter().zip(&bindings) {
+        writes.push(match field_static(&field.attrs)? {
+            Some(typename) => quote!(out.write_static(#typename, *#binding)?;),
+            None => quote!(::cconst::ToConstExpr::to_const_expr(#binding, out)?;),
+        });
+    }
+
+    let (pattern, open, prefixes, close) = match *fields {
+        Fields::Named(ref named) => {
+            let idents: Vec<_> = named.named.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+            let prefixes = idents.iter().map(|i| format!("{}: ", i)).collect();
+            (quote!(#pattern { #(#idents: ref #bindings),* }), " { ", prefixes, "}")
+        }
+        Fields::Unnamed(_) => {
+            let prefixes = bindings.iter().map(|_| String::new()).collect();
+            (quote!(#pattern(#(ref #bindings),*)), "(", prefixes, ")")
+        }
+        Fields::Unit => (pattern, "", Vec::new(), ""),
+    };
+    let open = format!("{}{}", path, open);
+
+    Ok(quote! {
+        #pattern => {
+            ::std::fmt::Write::write_str(out, #open)?;
+            #(
+                ::std::fmt::Write::write_str(out, #prefixes)?;
+                #writes
+                ::std::fmt::Write::write_str(out, ", ")?;
+            )*
+            ::std::fmt::Write::write_str(out, #close)
+        }
+    })
+}
+
+/// Reads the type set by a `#[const_expr(static = "...")]` field attribute.
+fn field_static(attrs: &[syn::Attribute]) -> Result<Option<String>, Error> {
+    let mut typename = None;
+
+    for attr in attrs {
+        if !attr.path().is_ident("const_expr") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("static") {
+                typename = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported `const_expr` field attribute"))
+            }
+        })?;
+    }
+
+    Ok(typename)
+}
+
+/// Reads the path set by a `#[const_expr(path = "...")]` attribute.
+fn expr_path(input: &DeriveInput) -> Result<Option<String>, Error> {
+    let mut path = None;
+
+    for attr in &input.attrs {
+        if !attr.path().is_ident("const_expr") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("path") {
+                path = Some(meta.value()?.parse::<LitStr>()?.value());
+                Ok(())
+            } else {
+                Err(meta.error("unsupported `const_expr` attribute"))
+            }
+        })?;
+    }
+
+    Ok(path)
+}
+
+/// Checks whether `ty` refers to one of the type parameters in `generics`.
+fn mentions_type_param(ty: &syn::Type, generics: &syn::Generics) -> bool {
+    fn visit(tokens: proc_macro2::TokenStream, params: &[&syn::Ident]) -> bool {
+        tokens.into_iter().any(|token| match token {
+            proc_macro2::TokenTree::Ident(ref ident) => params.contains(&ident),
+            proc_macro2::TokenTree::Group(ref group) => visit(group.stream(), params),
+            _ => false,
+        })
+    }
+
+    let params: Vec<_> = generics.type_params().map(|p| &p.ident).collect();
+    !params.is_empty() && visit(quote!(#ty), &params)
+}
+
+/// Checks for a `#[repr(C)]` or `#[repr(transparent)]` attribute.
+fn has_defined_layout(input: &DeriveInput) -> Result<bool, Error> {
+    let mut defined = false;
+
+    for attr in &input.attrs {
+        if !attr.path().is_ident("repr") {
+            continue;
+        }
+
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("C") || meta.path.is_ident("transparent") {
+                defined = true;
+            }
+
+            // skip arguments such as `align(8)`
+            if meta.input.peek(syn::token::Paren) {
+                let _content;
+                syn::parenthesized!(_content in meta.input);
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(defined)
+}
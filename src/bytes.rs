@@ -0,0 +1,181 @@
+//! Types that can be stored as byte images
+//!
+//! Only types implementing `ConstBytes` can be embedded using
+//! `CopyConsts::add_const`. Types holding pointers or references, like
+//! `&'static str` or function pointers, are `Copy` as well, but their values
+//! are addresses inside the build script process and meaningless in the final
+//! binary.
+
+use std::marker::PhantomData;
+use std::mem::{align_of, offset_of, size_of, transmute};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::num::{NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize,
+               NonZeroU128, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+               Wrapping};
+use std::time::Duration;
+
+use fingerprint;
+use layout::{FieldKind, Layout};
+
+/// Plain data that can be stored as a byte image
+///
+/// # Safety
+///
+/// Implementing types must not contain pointers, references or any other
+/// data whose meaning depends on the process it was created in. `layout` must
+/// mark every byte that is not padding as data, padding bytes are never read.
+pub unsafe trait ConstBytes: Copy {
+    /// Fingerprint identifying the type
+    ///
+    /// Checked at the `include!` site against the value seen by the build
+    /// script. Defaults to a combination of size and alignment, derived
+    /// implementations include the defining crate, the type's path and its
+    /// fields.
+    const FINGERPRINT: u64 = fingerprint::combine(fingerprint::combine(fingerprint::of_str(""),
+                                                                       size_of::<Self>() as u64),
+                                                  align_of::<Self>() as u64);
+
+    /// Layout of the type's memory representation
+    ///
+    /// Defaults to treating every byte as opaque data, which is only correct
+    /// for types without padding and prevents conversion for targets with a
+    /// different byte order.
+    fn layout() -> Layout {
+        Layout::dense(size_of::<Self>())
+    }
+}
+
+macro_rules! impl_with_layout {
+    ($layout:expr; $($ctype:ty),*) => ($(
+        unsafe impl ConstBytes for $ctype {
+            const FINGERPRINT: u64 = fingerprint::of_str(stringify!($ctype));
+
+            fn layout() -> Layout {
+                $layout(size_of::<Self>())
+            }
+        }
+    )*)
+}
+
+impl_with_layout!(Layout::scalar; u8, u16, u32, u64, u128, i8, i16, i32, i64, i128, f32, f64,
+                  bool, char, ());
+
+impl_with_layout!(Layout::scalar; NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU128,
+                  NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI128);
+
+// `None` is represented by the otherwise invalid zero value.
+impl_with_layout!(Layout::scalar; Option<NonZeroU8>, Option<NonZeroU16>, Option<NonZeroU32>,
+                  Option<NonZeroU64>, Option<NonZeroU128>, Option<NonZeroI8>,
+                  Option<NonZeroI16>, Option<NonZeroI32>, Option<NonZeroI64>,
+                  Option<NonZeroI128>);
+
+impl_with_layout!(|_| Layout::pointer_sized(); usize, isize, NonZeroUsize, NonZeroIsize,
+                  Option<NonZeroUsize>, Option<NonZeroIsize>);
+
+// Addresses are stored as octets in network byte order.
+impl_with_layout!(|size| {
+                      let mut layout = Layout::new(size);
+                      for i in 0..size {
+                          layout.typed_field(i, 1, FieldKind::Scalar);
+                      }
+                      layout
+                  };
+                  Ipv4Addr, Ipv6Addr);
+
+unsafe impl<T: ?Sized> ConstBytes for PhantomData<T> {
+    const FINGERPRINT: u64 = fingerprint::of_str("PhantomData");
+}
+
+unsafe impl<T: ConstBytes> ConstBytes for Wrapping<T> {
+    const FINGERPRINT: u64 = fingerprint::combine(fingerprint::of_str("Wrapping"),
+                                                  T::FINGERPRINT);
+
+    fn layout() -> Layout {
+        T::layout()
+    }
+}
+
+unsafe impl<T: ConstBytes, const N: usize> ConstBytes for [T; N] {
+    const FINGERPRINT: u64 = fingerprint::combine(fingerprint::combine(fingerprint::of_str("array"),
+                                                                       N as u64),
+                                                  T::FINGERPRINT);
+
+    fn layout() -> Layout {
+        let inner = T::layout();
+        let mut layout = Layout::new(size_of::<Self>());
+
+        for i in 0..N {
+            layout.nested(i * size_of::<T>(), &inner);
+        }
+
+        layout
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident: $idx:tt),*) => (
+        unsafe impl<$($name: ConstBytes),*> ConstBytes for ($($name,)*) {
+            const FINGERPRINT: u64 = {
+                let fp = fingerprint::of_str("tuple");
+                $(
+                    let fp = fingerprint::combine(fp, offset_of!(Self, $idx) as u64);
+                    let fp = fingerprint::combine(fp, $name::FINGERPRINT);
+                )*
+                fp
+            };
+
+            fn layout() -> Layout {
+                let mut layout = Layout::new(size_of::<Self>());
+                $(
+                    layout.nested(offset_of!(Self, $idx), &$name::layout());
+                )*
+                layout
+            }
+        }
+    )
+}
+
+impl_tuple!(A: 0);
+impl_tuple!(A: 0, B: 1);
+impl_tuple!(A: 0, B: 1, C: 2);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9, K: 10);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9, K: 10, L: 11);
+
+unsafe impl ConstBytes for Duration {
+    const FINGERPRINT: u64 = fingerprint::of_str("Duration");
+
+    fn layout() -> Layout {
+        // `Duration` holds a `u64` of seconds and a `u32` of nanoseconds in
+        // unspecified order. Instead of inspecting a value (and its padding),
+        // candidate images are converted into durations and compared.
+        assert_eq!(size_of::<Duration>(), 16, "unsupported `Duration` layout");
+
+        let expected = Duration::new(u64::from_ne_bytes([1, 0, 0, 0, 0, 0, 0, 0]),
+                                     u32::from_ne_bytes([2, 0, 0, 0]));
+
+        for &(secs_offset, nanos_offset) in &[(0, 8), (0, 12), (8, 0), (8, 4)] {
+            let mut image = [0u8; 16];
+            image[secs_offset] = 1;
+            image[nanos_offset] = 2;
+
+            // every four byte window of `image` holds a valid nanosecond count
+            let candidate: Duration = unsafe { transmute(image) };
+
+            if candidate == expected {
+                let mut layout = Layout::new(16);
+                layout.typed_field(secs_offset, 8, FieldKind::Scalar)
+                    .typed_field(nanos_offset, 4, FieldKind::Scalar);
+                return layout;
+            }
+        }
+
+        panic!("unsupported `Duration` layout")
+    }
+}
@@ -0,0 +1,511 @@
+//! Code generation for individual constants
+
+use error::Error;
+use ident;
+use layout::Layout;
+use target::Target;
+
+/// Kind of item generated for a constant
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    /// An accessor function `fn name() -> &'static T`
+    ///
+    /// This is the default.
+    Fn,
+    /// A constant `pub const name: T`, usable in `const` contexts like array
+    /// lengths, `const` generics and patterns
+    Const,
+    /// A static `pub static name: T`
+    Static,
+}
+
+/// Stored representation of a value
+#[derive(Debug)]
+pub(crate) enum Value {
+    /// Byte image of one or more values, the alignment required for them and
+    /// the identity of their type
+    Bytes {
+        image: Vec<u8>,
+        layout: Layout,
+        align: usize,
+        fingerprint: u64,
+        type_name: &'static str,
+        shape: Shape,
+    },
+    /// Rust expression evaluating to the value
+    Expr(String),
+    /// Literal evaluating to a `&'static` reference to the value
+    Literal(String),
+}
+
+/// How the values of a byte image are accessed
+#[derive(Debug)]
+pub(crate) enum Shape {
+    /// A single value
+    Value,
+    /// A slice of `len` values
+    Slice(usize),
+    /// A `Table` of `len` values, with the first at index `start`
+    Table {
+        len: usize,
+        index_type: String,
+        start: String,
+    },
+}
+
+/// Generated code for a constant
+pub(crate) struct Rendered {
+    pub(crate) code: String,
+    /// Size of the stored data in bytes, the source length for expressions
+    pub(crate) size: usize,
+    /// Contents of the side file included by `code`, if any
+    pub(crate) blob: Option<Vec<u8>>,
+}
+
+/// Expression evaluating to the `[u8; len]` of a byte image
+struct ByteArray {
+    len: usize,
+    expr: String,
+}
+
+/// Constant registered with `CopyConsts`
+#[derive(Debug)]
+pub struct Constant {
+    typename: String,
+    value: Value,
+    item: Item,
+    module: Vec<String>,
+    /// Whether the constant is part of a group
+    grouped: bool,
+}
+
+impl Constant {
+    pub(crate) fn new(typename: &str, value: Value) -> Constant {
+        Constant {
+            typename: typename.to_owned(),
+            value,
+            item: Item::Fn,
+            module: Vec::new(),
+            grouped: false,
+        }
+    }
+
+    pub(crate) fn set_grouped(&mut self, grouped: bool) {
+        self.grouped = grouped;
+    }
+
+    /// Set the kind of item generated.
+    pub fn item(&mut self, item: Item) -> &mut Constant {
+        self.item = item;
+        self
+    }
+
+    /// Place the constant in a submodule of the bundle, e.g. `"tables::crc"`.
+    ///
+    /// Only used when writing a bundle, see `CopyConsts::bundle`. Names of
+    /// constants must be unique regardless of their module. Accessor functions
+    /// in submodules are visible within the crate.
+    ///
+    /// Fails if a segment of `path` is not an identifier, including empty
+    /// segments as in `"a::"` or an empty path. Keywords are emitted
+    /// as raw identifiers. Writing the bundle fails if the outermost module
+    /// has the name of a group.
+    pub fn module(&mut self, path: &str) -> Result<&mut Constant, Error> {
+        self.module = path.split("::")
+            .map(|segment| ident::validate(segment).map(str::to_owned))
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    pub(crate) fn typename(&self) -> &str {
+        &self.typename
+    }
+
+    /// Path of the bundle submodule containing the constant
+    pub(crate) fn module_path(&self) -> &[String] {
+        &self.module
+    }
+
+    /// Name of the side file storing a large byte image, relative to
+    /// `OUT_DIR`
+    ///
+    /// `prefix` identifies the group the constant belongs to.
+    pub(crate) fn blob_name(&self, prefix: &str, fname: &str) -> String {
+        let mut name = prefix.to_owned() + "-";
+        for segment in &self.module {
+            name += segment;
+            name += "-";
+        }
+        name + fname + ".bin"
+    }
+
+    /// Generate code for the constant named `fname`.
+    ///
+    /// Byte images are converted for `target`, failing with a description of
+    /// the problem if that is not possible. Images larger than
+    /// `blob_threshold` bytes are returned separately, to be written to a
+    /// side file included using `include_bytes!`, named using `prefix`.
+    pub(crate) fn render(&self,
+                         prefix: &str,
+                         fname: &str,
+                         target: &Target,
+                         blob_threshold: usize)
+                         -> Result<Rendered, String> {
+        let mut code = String::new();
+        let mut blob = None;
+        let size;
+        let ident = ident::escape(fname);
+
+        match self.value {
+            Value::Bytes { ref image, ref layout, align, fingerprint, type_name, ref shape } => {
+                let image = target.convert(image, layout)?;
+                size = image.len();
+                let bytes = ByteArray {
+                    len: image.len(),
+                    expr: if image.len() > blob_threshold {
+                        format!("*include_bytes!(concat!(env!(\"OUT_DIR\"), \"/{}\"))",
+                                self.blob_name(prefix, fname))
+                    } else {
+                        byte_array_literal(&image)
+                    },
+                };
+
+                code += &layout_assertion(&self.typename, layout.size(), align);
+                code += &fingerprint_assertion(&self.typename, fingerprint, type_name);
+                code += &match *shape {
+                    Shape::Value => self.render_bytes(&ident, &bytes, align),
+                    Shape::Slice(len) => self.render_slice(&ident, &bytes, align, len),
+                    Shape::Table { len, ref index_type, ref start } => {
+                        self.render_table(&ident, &bytes, align, len, index_type, start)
+                    }
+                };
+
+                if image.len() > blob_threshold {
+                    blob = Some(image);
+                }
+            }
+            Value::Expr(ref expr) => {
+                size = expr.len();
+                code += &self.render_expr(&ident, expr);
+            }
+            Value::Literal(ref literal) => {
+                size = literal.len();
+                code += &self.render_literal(&ident, literal);
+            }
+        }
+
+        Ok(Rendered { code, size, blob })
+    }
+
+    /// Visibility of accessor functions, which need to be reachable from
+    /// outside of groups and bundle submodules
+    fn fn_visibility(&self) -> &'static str {
+        if self.grouped || !self.module.is_empty() { "pub(crate) " } else { "" }
+    }
+
+    fn render_bytes(&self, fname: &str, bytes: &ByteArray, align: usize) -> String {
+        let typename = &self.typename;
+
+        match self.item {
+            Item::Fn => {
+                format!("#[inline]\n{}fn {}() -> &'static {} {{
+    #[repr(C, align({}))]
+    struct Aligned([u8; {}]);
+    static BUF: Aligned = Aligned({});
+    unsafe {{ &*(BUF.0.as_ptr() as *const {}) }}
+}}\n",
+                        self.fn_visibility(),
+                        fname,
+                        typename,
+                        align,
+                        bytes.len,
+                        bytes.expr,
+                        typename)
+            }
+            item => {
+                // transmuting by value, so the alignment of the array is irrelevant
+                format!("#[allow(non_upper_case_globals, unknown_lints, unnecessary_transmutes)]
+pub {} {}: {} = unsafe {{
+    ::std::mem::transmute::<[u8; {}], {}>({})
+}};\n",
+                        item_keyword(item),
+                        fname,
+                        typename,
+                        bytes.len,
+                        typename,
+                        bytes.expr)
+            }
+        }
+    }
+
+    fn render_slice(&self, fname: &str, bytes: &ByteArray, align: usize, len: usize) -> String {
+        let typename = &self.typename;
+
+        match self.item {
+            Item::Fn => {
+                format!("#[inline]\n{}fn {}() -> &'static [{}] {{
+    const LEN: usize = {};
+    #[repr(C, align({}))]
+    struct Aligned([u8; {}]);
+    static BUF: Aligned = Aligned({});
+    unsafe {{ ::std::slice::from_raw_parts(BUF.0.as_ptr() as *const {}, LEN) }}
+}}\n",
+                        self.fn_visibility(),
+                        fname,
+                        typename,
+                        len,
+                        align,
+                        bytes.len,
+                        bytes.expr,
+                        typename)
+            }
+            item => {
+                format!("#[allow(non_upper_case_globals, unknown_lints, unnecessary_transmutes)]
+pub {} {}: &[{}] = &unsafe {{
+    ::std::mem::transmute::<[u8; {}], [{}; {}]>({})
+}};\n",
+                        item_keyword(item),
+                        fname,
+                        typename,
+                        bytes.len,
+                        typename,
+                        len,
+                        bytes.expr)
+            }
+        }
+    }
+
+    fn render_table(&self,
+                    fname: &str,
+                    bytes: &ByteArray,
+                    align: usize,
+                    len: usize,
+                    index_type: &str,
+                    start: &str)
+                    -> String {
+        let typename = &self.typename;
+        let table_type = format!("::cconst::Table<{}, {}>", index_type, typename);
+
+        match self.item {
+            Item::Fn => {
+                format!("#[inline]\n{}fn {}() -> &'static {} {{
+    const LEN: usize = {};
+    #[repr(C, align({}))]
+    struct Aligned([u8; {}]);
+    static BUF: Aligned = Aligned({});
+    static TABLE: {} = ::cconst::Table {{
+        start: {},
+        values: unsafe {{ ::std::slice::from_raw_parts(BUF.0.as_ptr() as *const {}, LEN) }},
+    }};
+    &TABLE
+}}\n",
+                        self.fn_visibility(),
+                        fname,
+                        table_type,
+                        len,
+                        align,
+                        bytes.len,
+                        bytes.expr,
+                        table_type,
+                        start,
+                        typename)
+            }
+            item => {
+                format!("#[allow(non_upper_case_globals, unknown_lints, unnecessary_transmutes)]
+pub {} {}: {} = ::cconst::Table {{
+    start: {},
+    values: &unsafe {{ ::std::mem::transmute::<[u8; {}], [{}; {}]>({}) }},
+}};\n",
+                        item_keyword(item),
+                        fname,
+                        table_type,
+                        start,
+                        bytes.len,
+                        typename,
+                        len,
+                        bytes.expr)
+            }
+        }
+    }
+
+    fn render_expr(&self, fname: &str, expr: &str) -> String {
+        let typename = &self.typename;
+
+        match self.item {
+            Item::Fn => {
+                format!("#[inline]\n{}fn {}() -> &'static {} {{
+    const VALUE: &{} = &{};
+    VALUE
+}}\n",
+                        self.fn_visibility(),
+                        fname,
+                        typename,
+                        typename,
+                        expr)
+            }
+            item => {
+                format!("#[allow(non_upper_case_globals)]\npub {} {}: {} = {};\n",
+                        item_keyword(item),
+                        fname,
+                        typename,
+                        expr)
+            }
+        }
+    }
+
+    fn render_literal(&self, fname: &str, literal: &str) -> String {
+        match self.item {
+            Item::Fn => {
+                format!("#[inline]\n{}fn {}() -> &'static {} {{\n    {}\n}}\n",
+                        self.fn_visibility(),
+                        fname,
+                        self.typename,
+                        literal)
+            }
+            item => {
+                format!("#[allow(non_upper_case_globals)]\npub {} {}: &{} = {};\n",
+                        item_keyword(item),
+                        fname,
+                        self.typename,
+                        literal)
+            }
+        }
+    }
+}
+
+fn item_keyword(item: Item) -> &'static str {
+    match item {
+        Item::Fn => "fn",
+        Item::Const => "const",
+        Item::Static => "static",
+    }
+}
+
+/// Renders a compile-time check of the size and alignment of `typename`.
+///
+/// Catches differences between the type seen by the build script and the one
+/// at the `include!` site, e.g. due to different versions of a dependency.
+fn layout_assertion(typename: &str, size: usize, align: usize) -> String {
+    let message = format!("cconst: layout of `{}` differs from the one seen by the build script",
+                          typename);
+
+    format!("const _: () = assert!(::std::mem::size_of::<{}>() == {} &&
+                      ::std::mem::align_of::<{}>() == {},
+                      {:?});\n",
+            typename,
+            size,
+            typename,
+            align,
+            message)
+}
+
+/// Renders a compile-time check of the `ConstBytes` fingerprint of `typename`.
+fn fingerprint_assertion(typename: &str, fingerprint: u64, type_name: &str) -> String {
+    let message = format!("cconst: `{}` is not the type `{}` seen by the build script",
+                          typename,
+                          type_name);
+
+    format!("const _: () = assert!(<{} as ::cconst::ConstBytes>::FINGERPRINT == {:#018x},
+                      {:?});\n",
+            typename,
+            fingerprint,
+            message)
+}
+
+/// Renders `bytes` as an expression evaluating to a `[u8; N]`.
+///
+/// Uses a dereferenced byte string literal, unless an array literal is shorter.
+fn byte_array_literal(bytes: &[u8]) -> String {
+    let literal = format!("*{}", byte_string_literal(bytes));
+
+    // `0xNN, ` per byte, enclosed in brackets
+    if literal.len() <= bytes.len() * 6 + 2 {
+        return literal;
+    }
+
+    let mut rexpr = String::new();
+    rexpr += "[";
+
+    for byte in bytes {
+        rexpr.push_str(&format!("0x{:02X}, ", byte));
+    }
+
+    rexpr += "]";
+
+    rexpr
+}
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// Renders `bytes` as a byte string literal.
+pub(crate) fn byte_string_literal(bytes: &[u8]) -> String {
+    let mut literal = String::with_capacity(bytes.len() + 3);
+    literal += "b\"";
+
+    for &byte in bytes {
+        match byte {
+            b'"' => literal += "\\\"",
+            b'\\' => literal += "\\\\",
+            b'\0' => literal += "\\0",
+            b'\n' => literal += "\\n",
+            b'\r' => literal += "\\r",
+            b'\t' => literal += "\\t",
+            b' '..=b'~' => literal.push(byte as char),
+            _ => {
+                literal += "\\x";
+                literal.push(HEX_DIGITS[(byte >> 4) as usize] as char);
+                literal.push(HEX_DIGITS[(byte & 0xf) as usize] as char);
+            }
+        }
+    }
+
+    literal += "\"";
+
+    literal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn module_paths() {
+        let mut constant = Constant::new("u8", Value::Literal("1".to_owned()));
+
+        constant.module("a::r#fn::b").unwrap();
+        assert_eq!(constant.module_path(), ["a", "fn", "b"]);
+        assert_eq!(constant.blob_name("cconst", "x"), "cconst-a-fn-b-x.bin");
+
+        for &path in &["", "a::::b", "::a", "a::", "a b", "a::self"] {
+            assert!(constant.module(path).is_err(), "{:?}", path);
+        }
+        assert_eq!(constant.module_path(), ["a", "fn", "b"]);
+    }
+
+    #[test]
+    fn byte_string_of_all_bytes() {
+        let bytes: Vec<u8> = (0..=255).collect();
+
+        let mut expected = String::from(r#"b""#);
+        expected += r"\0\x01\x02\x03\x04\x05\x06\x07\x08\t\n\x0b\x0c\r\x0e\x0f";
+        expected += r"\x10\x11\x12\x13\x14\x15\x16\x17\x18\x19\x1a\x1b\x1c\x1d\x1e\x1f";
+        expected += r##" !\"#$%&'()*+,-./0123456789:;<=>?"##;
+        expected += r"@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_";
+        expected += r"`abcdefghijklmnopqrstuvwxyz{|}~\x7f";
+        for byte in 0x80..=0xff {
+            expected += &format!(r"\x{:02x}", byte);
+        }
+        expected += "\"";
+
+        assert_eq!(byte_string_literal(&bytes), expected);
+    }
+
+    #[test]
+    fn byte_array_uses_shorter_form() {
+        // `*b""` is longer than `[]`
+        assert_eq!(byte_array_literal(&[]), "[]");
+        // `*b"\xff"` is as long as `[0xFF, ]`
+        assert_eq!(byte_array_literal(&[0xff]), r#"*b"\xff""#);
+        assert_eq!(byte_array_literal(b"\0\"\\"), r#"*b"\0\"\\""#);
+    }
+}
@@ -0,0 +1,80 @@
+//! Errors of `CopyConsts`
+
+use std::path::PathBuf;
+use std::{error, fmt, io};
+
+use typename::UnresolvedType;
+
+/// Error adding or writing constants
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// `OUT_DIR` is not set, i.e. not running as a build script
+    MissingOutDir,
+    /// Name of a constant that is not a valid identifier
+    InvalidIdentifier(String),
+    /// Name of a constant that was already added
+    DuplicateName(String),
+    /// Name of a bundle submodule that is also the name of a group
+    DuplicateModule(String),
+    /// Name of a table whose range does not include its start
+    InvalidRange(String),
+    /// Type of a constant that cannot be named at the `include!` site
+    UnresolvedType(UnresolvedType),
+    /// Value that cannot be represented on the target
+    UnsupportedLayout {
+        name: String,
+        target: String,
+        reason: String,
+    },
+    /// Failure reading or writing a file
+    Io { path: PathBuf, source: io::Error },
+}
+
+impl Error {
+    pub(crate) fn io<P: Into<PathBuf>>(path: P, source: io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::MissingOutDir => {
+                f.write_str("`OUT_DIR` is not set, not running as a build script")
+            }
+            Error::InvalidIdentifier(ref name) => write!(f, "`{}` is not a valid identifier", name),
+            Error::DuplicateName(ref name) => write!(f, "constant `{}` already added", name),
+            Error::DuplicateModule(ref name) => {
+                write!(f, "module `{}` is both a group and a bundle submodule", name)
+            }
+            Error::InvalidRange(ref name) => {
+                write!(f, "range of table `{}` does not include its start", name)
+            }
+            Error::UnresolvedType(ref e) => e.fmt(f),
+            Error::UnsupportedLayout { ref name, ref target, ref reason } => {
+                write!(f, "cannot store `{}` for target `{}`: {}", name, target, reason)
+            }
+            Error::Io { ref path, ref source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::UnresolvedType(ref e) => Some(e),
+            Error::Io { ref source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<UnresolvedType> for Error {
+    fn from(e: UnresolvedType) -> Error {
+        Error::UnresolvedType(e)
+    }
+}
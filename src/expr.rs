@@ -0,0 +1,283 @@
+//! Rendering values as Rust expressions
+//!
+//! Values implementing `ToConstExpr` are stored as Rust source instead of a
+//! byte image. The generated code contains no `unsafe`, and is type-checked
+//! by the compiler at the `include!` site.
+
+use std::fmt::{self, Write};
+use std::mem;
+use std::marker::PhantomData;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::num::{NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroIsize,
+               NonZeroU128, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU8, NonZeroUsize,
+               Wrapping};
+use std::time::Duration;
+
+/// Prefix of the names of statics written by `ExprWriter::write_static`
+const STATIC_PREFIX: &str = "__CCONST_STATIC_";
+
+/// Output for rendered Rust expressions
+#[derive(Debug, Default)]
+pub struct ExprWriter {
+    code: String,
+    /// Type and expression of each static written by `write_static`
+    statics: Vec<(String, String)>,
+}
+
+impl ExprWriter {
+    /// Create an empty writer.
+    pub fn new() -> ExprWriter {
+        ExprWriter::default()
+    }
+
+    /// Render `val` and return the resulting source.
+    ///
+    /// If statics were written, the expression is a block defining them.
+    pub fn render<T: ToConstExpr + ?Sized>(val: &T) -> String {
+        let mut out = ExprWriter::new();
+        val.to_const_expr(&mut out)
+            .expect("writing to a string cannot fail");
+        out.finish()
+    }
+
+    /// Return the source written so far, including statics.
+    pub(crate) fn finish(self) -> String {
+        if self.statics.is_empty() {
+            return self.code;
+        }
+
+        let mut block = "{\n".to_owned();
+        for (i, (typename, expr)) in self.statics.iter().enumerate() {
+            block += &format!("    static {}{}: &'static {} = &{};\n",
+                              STATIC_PREFIX,
+                              i,
+                              typename,
+                              expr);
+        }
+        block += &format!("    {}\n}}", self.code);
+        block
+    }
+
+    /// Write a reference to `val`, stored in a separate `static` item.
+    ///
+    /// The written expression is a `&'static T`, with `typename` naming `T`.
+    /// Statics with equal type and value are only emitted once, so data
+    /// referenced from several places is shared.
+    pub fn write_static<T: ToConstExpr + ?Sized>(&mut self, typename: &str, val: &T) -> fmt::Result {
+        // nested statics are collected in the same list, ahead of this one
+        let mut inner = ExprWriter {
+            code: String::new(),
+            statics: mem::take(&mut self.statics),
+        };
+        let result = val.to_const_expr(&mut inner);
+        self.statics = inner.statics;
+        result?;
+
+        let key = (typename.to_owned(), inner.code);
+        let index = match self.statics.iter().position(|s| *s == key) {
+            Some(index) => index,
+            None => {
+                self.statics.push(key);
+                self.statics.len() - 1
+            }
+        };
+
+        write!(self, "{}{}", STATIC_PREFIX, index)
+    }
+
+    /// Write a comma separated list of expressions.
+    pub fn write_list<'a, T, I>(&mut self, items: I) -> fmt::Result
+        where T: ToConstExpr + 'a,
+              I: IntoIterator<Item = &'a T>
+    {
+        for item in items {
+            item.to_const_expr(self)?;
+            self.write_str(", ")?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for ExprWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.code.push_str(s);
+        Ok(())
+    }
+}
+
+/// Values that can be written as a constant Rust expression
+///
+/// The rendered expression must be valid in a `const` context and evaluate to
+/// a value equal to `self`. Paths should be fully qualified, as the code is
+/// compiled at the `include!` site.
+pub trait ToConstExpr {
+    /// Write an expression constructing `self` to `out`.
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result;
+}
+
+macro_rules! impl_int {
+    ($($ctype:ident),*) => ($(
+        impl ToConstExpr for $ctype {
+            fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+                write!(out, "{}{}", self, stringify!($ctype))
+            }
+        }
+    )*)
+}
+
+impl_int!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize);
+
+macro_rules! impl_float {
+    ($($ctype:ident),*) => ($(
+        impl ToConstExpr for $ctype {
+            fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+                if self.is_finite() {
+                    // `Debug` output is the shortest representation that
+                    // round-trips exactly
+                    write!(out, "{:?}{}", self, stringify!($ctype))
+                } else {
+                    write!(out, "{}::from_bits({:#x})", stringify!($ctype), self.to_bits())
+                }
+            }
+        }
+    )*)
+}
+
+impl_float!(f32, f64);
+
+macro_rules! impl_nonzero {
+    ($($ctype:ident),*) => ($(
+        impl ToConstExpr for $ctype {
+            fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+                write!(out, "::std::num::{}::new(", stringify!($ctype))?;
+                self.get().to_const_expr(out)?;
+                out.write_str(").unwrap()")
+            }
+        }
+    )*)
+}
+
+impl_nonzero!(NonZeroU8, NonZeroU16, NonZeroU32, NonZeroU64, NonZeroU128, NonZeroUsize,
+              NonZeroI8, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI128, NonZeroIsize);
+
+impl ToConstExpr for bool {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        write!(out, "{}", self)
+    }
+}
+
+impl ToConstExpr for char {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        write!(out, "{:?}", self)
+    }
+}
+
+impl ToConstExpr for str {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        // a string literal is a `&str` already, dereference it to get a `str`
+        write!(out, "*{:?}", self)
+    }
+}
+
+impl<T: ToConstExpr + ?Sized> ToConstExpr for &T {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        out.write_str("&")?;
+        (**self).to_const_expr(out)
+    }
+}
+
+impl<T: ToConstExpr> ToConstExpr for [T] {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        out.write_str("[")?;
+        out.write_list(self)?;
+        out.write_str("]")
+    }
+}
+
+impl<T: ToConstExpr, const N: usize> ToConstExpr for [T; N] {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        self[..].to_const_expr(out)
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident: $idx:tt),*) => (
+        impl<$($name: ToConstExpr),*> ToConstExpr for ($($name,)*) {
+            fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+                out.write_str("(")?;
+                $(
+                    self.$idx.to_const_expr(out)?;
+                    out.write_str(", ")?;
+                )*
+                out.write_str(")")
+            }
+        }
+    )
+}
+
+impl_tuple!();
+impl_tuple!(A: 0);
+impl_tuple!(A: 0, B: 1);
+impl_tuple!(A: 0, B: 1, C: 2);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9, K: 10);
+impl_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9, K: 10, L: 11);
+
+impl<T: ToConstExpr> ToConstExpr for Option<T> {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        match *self {
+            Some(ref val) => {
+                out.write_str("::std::option::Option::Some(")?;
+                val.to_const_expr(out)?;
+                out.write_str(")")
+            }
+            None => out.write_str("::std::option::Option::None"),
+        }
+    }
+}
+
+impl<T: ToConstExpr> ToConstExpr for Wrapping<T> {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        out.write_str("::std::num::Wrapping(")?;
+        self.0.to_const_expr(out)?;
+        out.write_str(")")
+    }
+}
+
+impl<T: ?Sized> ToConstExpr for PhantomData<T> {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        out.write_str("::std::marker::PhantomData")
+    }
+}
+
+impl ToConstExpr for Ipv4Addr {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        out.write_str("::std::net::Ipv4Addr::new(")?;
+        out.write_list(&self.octets())?;
+        out.write_str(")")
+    }
+}
+
+impl ToConstExpr for Ipv6Addr {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        out.write_str("::std::net::Ipv6Addr::new(")?;
+        out.write_list(&self.segments())?;
+        out.write_str(")")
+    }
+}
+
+impl ToConstExpr for Duration {
+    fn to_const_expr(&self, out: &mut ExprWriter) -> fmt::Result {
+        out.write_str("::std::time::Duration::new(")?;
+        self.as_secs().to_const_expr(out)?;
+        out.write_str(", ")?;
+        self.subsec_nanos().to_const_expr(out)?;
+        out.write_str(")")
+    }
+}
@@ -0,0 +1,35 @@
+//! Type fingerprints
+//!
+//! Every `ConstBytes` type carries a `FINGERPRINT`, a hash identifying the
+//! type beyond its size and alignment. It is recorded by the build script and
+//! checked at compile time wherever the constant is included, which catches
+//! types that changed between the versions of a crate used as a build
+//! dependency and as a regular dependency.
+//!
+//! The functions in this module are `const` and meant for computing
+//! fingerprints in manual `ConstBytes` implementations.
+
+pub(crate) const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Fingerprint of a string, e.g. a type name
+pub const fn of_str(s: &str) -> u64 {
+    extend(FNV_OFFSET, s.as_bytes())
+}
+
+/// Combine a fingerprint with another value
+pub const fn combine(fingerprint: u64, value: u64) -> u64 {
+    extend(fingerprint, &value.to_le_bytes())
+}
+
+pub(crate) const fn extend(mut hash: u64, bytes: &[u8]) -> u64 {
+    let mut i = 0;
+
+    while i < bytes.len() {
+        hash ^= bytes[i] as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+
+    hash
+}
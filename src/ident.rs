@@ -0,0 +1,118 @@
+//! Names of constants
+//!
+//! Constant names become both items in the generated code and parts of file
+//! names, they are restricted to ASCII identifiers. Keywords are allowed and
+//! emitted as raw identifiers, e.g. a constant `fn` is accessed as `r#fn()`.
+//! Group names are restricted further, to identifiers that are not keywords.
+
+use error::Error;
+
+/// Keywords of any edition, usable as raw identifiers
+const KEYWORDS: &[&str] = &["abstract", "as", "async", "await", "become", "box", "break", "const",
+                            "continue", "do", "dyn", "else", "enum", "extern", "false", "final",
+                            "fn", "for", "gen", "if", "impl", "in", "let", "loop", "macro",
+                            "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+                            "return", "static", "struct", "trait", "true", "try", "type",
+                            "typeof", "unsafe", "unsized", "use", "virtual", "where", "while",
+                            "yield"];
+
+/// Keywords that cannot be raw identifiers
+const RESERVED: &[&str] = &["_", "crate", "self", "Self", "super"];
+
+/// Validate the name of a constant, returning it without a leading `r#`.
+pub(crate) fn validate(name: &str) -> Result<&str, Error> {
+    let ident = name.strip_prefix("r#").unwrap_or(name);
+    let mut chars = ident.chars();
+
+    let valid = match chars.next() {
+        Some(c) => c.is_ascii_alphabetic() || c == '_',
+        None => false,
+    };
+
+    if !valid || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') ||
+       RESERVED.contains(&ident) {
+        return Err(Error::InvalidIdentifier(name.to_owned()));
+    }
+
+    Ok(ident)
+}
+
+/// Validate the name of a group.
+///
+/// Unlike constants, groups cannot be named by keywords, the name is used as
+/// is by `cconst_group!`.
+pub(crate) fn validate_group(name: &str) -> Result<&str, Error> {
+    match validate(name) {
+        Ok(ident) if ident == name && !KEYWORDS.contains(&ident) => Ok(ident),
+        Ok(_) => Err(Error::InvalidIdentifier(name.to_owned())),
+        Err(e) => Err(e),
+    }
+}
+
+/// Identifier referring to a validated name in generated code
+pub(crate) fn escape(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_invalid(name: &str) -> bool {
+        match validate(name) {
+            Err(Error::InvalidIdentifier(ref invalid)) => invalid == name,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn accepts_identifiers() {
+        assert_eq!(validate("a").ok(), Some("a"));
+        assert_eq!(validate("_a1").ok(), Some("_a1"));
+        assert_eq!(validate("Table_2").ok(), Some("Table_2"));
+        assert_eq!(validate("union").ok(), Some("union"));
+    }
+
+    #[test]
+    fn strips_raw_prefix() {
+        assert_eq!(validate("r#fn").ok(), Some("fn"));
+        assert_eq!(validate("r#abc").ok(), Some("abc"));
+        assert!(is_invalid("r#"));
+        assert!(is_invalid("r#r#fn"));
+    }
+
+    #[test]
+    fn refuses_reserved_names() {
+        for &name in &["_", "self", "Self", "super", "crate", "r#crate", "r#self", "r#_"] {
+            assert!(is_invalid(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn refuses_malformed_names() {
+        for &name in &["", "1a", "0", "my-const", "a b", "a::b", "a.b", "ä", "naïve", "a\0"] {
+            assert!(is_invalid(name), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn escapes_keywords() {
+        assert_eq!(escape("fn"), "r#fn");
+        assert_eq!(escape("async"), "r#async");
+        assert_eq!(escape("gen"), "r#gen");
+        assert_eq!(escape("union"), "union");
+        assert_eq!(escape("value"), "value");
+    }
+
+    #[test]
+    fn group_names_are_not_keywords() {
+        assert_eq!(validate_group("net").ok(), Some("net"));
+        assert!(validate_group("fn").is_err());
+        assert!(validate_group("r#net").is_err());
+        assert!(validate_group("my-grp").is_err());
+    }
+}
@@ -0,0 +1,139 @@
+//! Memory layout descriptions
+//!
+//! A `Layout` records which bytes of a value hold actual data. All other bytes
+//! are considered padding, which is never read while marshalling and always
+//! emitted as zero.
+//!
+//! Data bytes are further described by the kind of field they belong to,
+//! which allows converting values for targets with a different byte order.
+
+use std::mem::size_of;
+
+use bytes::ConstBytes;
+
+/// Interpretation of the bytes of a field
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FieldKind {
+    /// Bytes of unknown structure
+    ///
+    /// Values containing opaque fields larger than a single byte cannot be
+    /// converted for targets with a different byte order.
+    Opaque,
+    /// An integer or floating point number in native byte order
+    Scalar,
+    /// A scalar whose size is the target's pointer width, like `usize`
+    PointerSized,
+}
+
+/// Data bytes inside a `Layout`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Field {
+    pub(crate) offset: usize,
+    pub(crate) size: usize,
+    pub(crate) kind: FieldKind,
+}
+
+/// Data/padding description of a type's memory representation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Layout {
+    size: usize,
+    fields: Vec<Field>,
+}
+
+impl Layout {
+    /// Create a layout of `size` bytes, all of which are padding.
+    pub fn new(size: usize) -> Layout {
+        Layout {
+            size,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Create a layout of `size` opaque bytes without any padding.
+    pub fn dense(size: usize) -> Layout {
+        let mut layout = Layout::new(size);
+        layout.field(0, size);
+        layout
+    }
+
+    /// Create a layout consisting of a single scalar of `size` bytes.
+    pub fn scalar(size: usize) -> Layout {
+        let mut layout = Layout::new(size);
+        layout.typed_field(0, size, FieldKind::Scalar);
+        layout
+    }
+
+    /// Create a layout consisting of a single pointer-sized scalar.
+    pub fn pointer_sized() -> Layout {
+        let mut layout = Layout::new(size_of::<usize>());
+        layout.typed_field(0, size_of::<usize>(), FieldKind::PointerSized);
+        layout
+    }
+
+    /// Size of the described type in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Mark `size` bytes starting at `offset` as opaque data.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field does not fit into the layout.
+    pub fn field(&mut self, offset: usize, size: usize) -> &mut Layout {
+        self.typed_field(offset, size, FieldKind::Opaque)
+    }
+
+    /// Mark `size` bytes starting at `offset` as data of the given kind.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field does not fit into the layout.
+    pub fn typed_field(&mut self, offset: usize, size: usize, kind: FieldKind) -> &mut Layout {
+        assert!(offset + size <= self.size,
+                "field at {}..{} exceeds layout size {}",
+                offset,
+                offset + size,
+                self.size);
+
+        if size > 0 {
+            self.fields.push(Field { offset, size, kind });
+        }
+        self
+    }
+
+    /// Embed the data bytes of `inner` at `offset`.
+    ///
+    /// Used to describe fields that contain padding themselves.
+    pub fn nested(&mut self, offset: usize, inner: &Layout) -> &mut Layout {
+        for field in &inner.fields {
+            self.typed_field(offset + field.offset, field.size, field.kind);
+        }
+        self
+    }
+
+    pub(crate) fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    /// Returns a mask with one entry per byte, `true` for data bytes.
+    pub fn data_mask(&self) -> Vec<bool> {
+        let mut mask = vec![false; self.size];
+
+        for field in &self.fields {
+            for flag in &mut mask[field.offset..field.offset + field.size] {
+                *flag = true;
+            }
+        }
+
+        mask
+    }
+}
+
+/// Returns the layout of the field selected by `_select`.
+///
+/// Helper for the `layout!` macro.
+#[doc(hidden)]
+pub fn field_layout<T, F: ConstBytes, S: Fn(&T) -> &F>(_select: S) -> Layout {
+    F::layout()
+}
@@ -1,19 +1,19 @@
 //! Build-time evaluated expressions
 //!
 //! `cconst` allows defining constants at build time of any type that
-//! implements the `Copy` trait. Values are generated through `build.rs`:
+//! implements `ConstBytes`, i.e. `Copy` data holding no pointers or
+//! references. Values are generated through `build.rs`:
 //!
-//! ```
+//! ```no_run
 //! // build.rs
 //! #[macro_use]
 //! extern crate cconst;
 //!
+//! use cconst::CopyConsts;
 //! use std::net::Ipv4Addr;
 //!
 //! let mut cs = CopyConsts::new();
-//! cs.add_const("default_ns", "::std::net::Ipv4Addr", {
-//!     Ipv4Addr::new(8, 8, 8, 8)
-//! });
+//! cs.add("default_ns", &Ipv4Addr::new(8, 8, 8, 8)).unwrap();
 //! cs.write_code().unwrap();
 //!
 //! ```
@@ -35,34 +35,358 @@
 //! # Internals
 //!
 //! `cconst` works by serializing the value defined in `build.rs` into
-//! byte-slice literals and including those where applicable. The example above
+//! byte string literals and including those where applicable. The example above
 //! results in roughly the following generated code:
 //!
 //! ```ignore
+//! const _: () = assert!(::std::mem::size_of::<::std::net::Ipv4Addr>() == 4 &&
+//!                       ::std::mem::align_of::<::std::net::Ipv4Addr>() == 1,
+//!                       "cconst: layout of `::std::net::Ipv4Addr` differs ...");
 //! #[inline]
 //! fn default_ns() -> &'static ::std::net::Ipv4Addr {
-//!     const BUF: &[u8] = &[0x08, 0x08, 0x08, 0x08, ];
-//!     unsafe { &*(BUF.as_ptr() as *const ::std::net::Ipv4Addr) }
+//!     #[repr(C, align(1))]
+//!     struct Aligned([u8; 4]);
+//!     static BUF: Aligned = Aligned(*b"\x08\x08\x08\x08");
+//!     unsafe { &*(BUF.0.as_ptr() as *const ::std::net::Ipv4Addr) }
 //! }
 //! ```
 //!
+//! The byte buffer is wrapped in a struct carrying the alignment of the
+//! original type, as recorded by `add_const`, making the pointer cast sound.
+//! Size and alignment are checked against the type at the `include!` site
+//! during compilation.
 //! Calling `default_ns()` should result in an inlined pointer cast and little,
 //! if any overhead.
 //!
+//! ## Supported types
+//!
+//! Only types implementing the `ConstBytes` trait can be stored. It is
+//! implemented for primitives, arrays, tuples and a few plain data types from
+//! `std`. Types containing pointers or references are rejected, since they
+//! would store addresses of the build script process:
+//!
+//! ```compile_fail
+//! # use cconst::CopyConsts;
+//! let mut cs = CopyConsts::new();
+//! cs.add_const("greeting", "&'static str", &"Hello, world").unwrap();
+//! ```
+//!
+//! Strings and byte slices can be stored using `add_str` and `add_bytes`, see
+//! below.
+//!
+//! ## Slices
+//!
+//! Tables whose length is only known when the build script runs can be stored
+//! using `add_slice`:
+//!
+//! ```no_run
+//! # use cconst::CopyConsts;
+//! let squares: Vec<u32> = (0..256).map(|i| i * i).collect();
+//!
+//! let mut cs = CopyConsts::new();
+//! cs.add_slice("squares", &squares).unwrap();
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! The elements are stored back to back in an aligned array, and
+//! `include!(cconst!(squares))` results in a function returning a
+//! `&'static [u32]` of the recorded length.
+//!
+//! Tables computed from their index, such as CRC or gamma tables, can be
+//! generated using `add_table`:
+//!
+//! ```no_run
+//! # use cconst::CopyConsts;
+//! let mut cs = CopyConsts::new();
+//! cs.add_table("gamma", 0u8..=255, |i| ((i as f32 / 255.0).powf(2.2) * 255.0) as u8)
+//!     .unwrap();
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! The result is a `&'static Table<u8, u8>`, indexed by `u8` and aware of the
+//! range it covers.
+//!
+//! Byte images larger than 64 KiB are not written as literals, but stored in a
+//! `.bin` file in `OUT_DIR` and included using `include_bytes!`, as rustc
+//! parses large array literals slowly. The size limit can be changed using
+//! `CopyConsts::blob_threshold`.
+//!
+//! Strings and byte strings are stored as literals instead, using `add_str`
+//! and `add_bytes`:
+//!
+//! ```no_run
+//! # use cconst::CopyConsts;
+//! let mut cs = CopyConsts::new();
+//! cs.add_str("banner", &format!("built with {} features", 3)).unwrap();
+//! cs.add_bytes("magic", &[0x7f, b'E', b'L', b'F']).unwrap();
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! These result in functions returning a `&'static str` and a
+//! `&'static [u8]`, respectively.
+//!
+//! ## Padding
+//!
+//! Only the bytes marked as data by `ConstBytes::layout` are read from a value,
+//! padding bytes are emitted as zeros. This avoids reading uninitialized memory
+//! and makes the generated code reproducible.
+//!
+//! With the `derive` feature enabled, `ConstBytes` can be derived for
+//! `#[repr(C)]` structs. Alternatively, custom types can implement it by hand,
+//! describing their layout using the `layout!` macro:
+//!
+//! ```no_run
+//! #[macro_use]
+//! extern crate cconst;
+//!
+//! use cconst::{ConstBytes, CopyConsts, Layout};
+//!
+//! #[derive(Copy, Clone)]
+//! #[repr(C)]
+//! struct Entry {
+//!     id: u8,
+//!     offset: u32,
+//! }
+//!
+//! unsafe impl ConstBytes for Entry {
+//!     fn layout() -> Layout {
+//!         layout!(Entry { id, offset })
+//!     }
+//! }
+//!
+//! # fn main() {
+//! let mut cs = CopyConsts::new();
+//! cs.add_const("entry", "::mycrate::Entry", &Entry { id: 1, offset: 0x100 }).unwrap();
+//! cs.write_code().unwrap();
+//! # }
+//! ```
+//!
+//! ## Expressions
+//!
+//! As an alternative to storing byte images, values implementing
+//! `ToConstExpr` can be rendered as Rust source by `CopyConsts::add_expr`:
+//!
+//! ```no_run
+//! # use cconst::CopyConsts;
+//! # use std::net::Ipv4Addr;
+//! let mut cs = CopyConsts::new();
+//! cs.add_expr("default_ns", "::std::net::Ipv4Addr", &Ipv4Addr::new(8, 8, 8, 8)).unwrap();
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! The generated code is free of `unsafe` and checked by the compiler at the
+//! `include!` site:
+//!
+//! ```ignore
+//! #[inline]
+//! fn default_ns() -> &'static ::std::net::Ipv4Addr {
+//!     const VALUE: &::std::net::Ipv4Addr = &::std::net::Ipv4Addr::new(8u8, 8u8, 8u8, 8u8, );
+//!     VALUE
+//! }
+//! ```
+//!
+//! `ToConstExpr` can be derived with the `derive` feature. Since a struct
+//! literal is generated, all fields must be visible at the `include!` site.
+//! The path used for the type defaults to its name and can be overridden
+//! using `#[const_expr(path = "::mycrate::Type")]`.
+//!
+//! ## References
+//!
+//! Byte images cannot contain pointers, as addresses of the build script
+//! process are meaningless at the `include!` site. Nested `&'static` data,
+//! e.g. a struct holding a `&'static str` or a `&'static [Entry]`, is stored
+//! using the expression backend instead. By default, referenced data is
+//! inlined into the expression. Fields marked with
+//! `#[const_expr(static = "...")]` are stored in separate `static` items
+//! referring to each other, with equal data emitted only once:
+//!
+//! ```ignore
+//! #[derive(ToConstExpr)]
+//! #[const_expr(path = "::mycrate::Node")]
+//! pub struct Node {
+//!     pub name: &'static str,
+//!     #[const_expr(static = "[::mycrate::Node]")]
+//!     pub children: &'static [Node],
+//! }
+//! ```
+//!
+//! Manual `ToConstExpr` implementations can use `ExprWriter::write_static`.
+//!
+//! ## Maps
+//!
+//! `add_map` computes a perfect hash function for a fixed set of keys,
+//! replacing `HashMap`s built at startup:
+//!
+//! ```no_run
+//! # use cconst::CopyConsts;
+//! let mut cs = CopyConsts::new();
+//! cs.add_map("status_codes",
+//!            "&'static str",
+//!            "u16",
+//!            vec![("ok", 200u16), ("not_found", 404), ("teapot", 418)])
+//!     .unwrap();
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! After `include!(cconst!(status_codes))`, `status_codes()` returns a
+//! `&'static cconst::Map<&'static str, u16>`, which supports `get`,
+//! `contains_key` and iteration, without building a table at startup.
+//!
+//! Where keys need to be iterated in order or queried by range, `add_set` and
+//! `add_sorted_table` sort them in the build script instead, resulting in a
+//! `Set` or `SortedTable` searched using binary search:
+//!
+//! ```no_run
+//! # use cconst::CopyConsts;
+//! let mut cs = CopyConsts::new();
+//! cs.add_set("reserved", "&'static str", vec!["while", "fn", "if", "fn"]).unwrap();
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! ## Cross-compilation
+//!
+//! Byte images are taken on the host running the build script. When
+//! cross-compiling, `write_code` converts them for the target reported by
+//! cargo: scalars described by a type's layout (see `FieldKind`) are
+//! byte-swapped if the target's byte order differs. Values that cannot be
+//! converted cause an error instead of silently producing wrong constants;
+//! this is the case for opaque multi-byte fields when the byte order differs
+//! and for pointer-sized fields (`usize`, `isize`) when the pointer width
+//! differs. Constants rendered as expressions are not affected.
+//!
+//! ## Items
+//!
+//! By default, each constant is accessed through a function returning a
+//! `&'static` reference. Since function calls cannot be used in all `const`
+//! contexts, a constant can instead be generated as a `const` or `static`
+//! item:
+//!
+//! ```no_run
+//! # use cconst::{CopyConsts, Item};
+//! let mut cs = CopyConsts::new();
+//! cs.add_const("TABLE_SIZE", "usize", &64usize).unwrap().item(Item::Const);
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! After `include!(cconst!(TABLE_SIZE))`, the result can be used like any
+//! other constant, e.g. as `[u8; TABLE_SIZE]`. Byte images are converted using
+//! `std::mem::transmute` inside the item's initializer.
+//!
+//! ## Bundles
+//!
+//! Crates with many constants can write all of them into a single module,
+//! optionally arranged in submodules, and include it using one `cconst_all!()`
+//! instead of one `include!` per constant:
+//!
+//! ```no_run
+//! # use cconst::CopyConsts;
+//! let mut cs = CopyConsts::new();
+//! cs.bundle();
+//! cs.add("answer", &42u32).unwrap();
+//! cs.add_table("squares", 0u8..16, |i| i as u32 * i as u32)
+//!     .unwrap()
+//!     .module("tables")
+//!     .unwrap();
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! After `cconst_all!()`, the constants are available as `answer()` and
+//! `tables::squares()`.
+//!
+//! ## Groups
+//!
+//! All constants added to a `CopyConsts` share one namespace. Subsystems can
+//! own their constants in separate groups instead, which map to Rust modules
+//! written to files of their own:
+//!
+//! ```no_run
+//! # use cconst::CopyConsts;
+//! let mut cs = CopyConsts::new();
+//! cs.group("net").unwrap().add("timeout_ms", &500u32).unwrap();
+//! cs.group("ui").unwrap().add("timeout_ms", &2000u32).unwrap();
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! `cconst_group!(net)` then defines a module `net` containing `timeout_ms()`.
+//! Groups of a bundle are included by `cconst_all!()`. Group names must be
+//! identifiers other than keywords.
+//!
+//! ## Dependencies
+//!
+//! By default, cargo reruns a build script whenever any file of the package
+//! changes. Registering the actual inputs of the constants makes it rerun
+//! exactly when needed:
+//!
+//! ```no_run
+//! # use cconst::CopyConsts;
+//! let mut cs = CopyConsts::new();
+//! cs.depends_on_file("data/words.txt")
+//!     .depends_on_env("WORDLIST_VARIANT");
+//! // ... add constants computed from these
+//! cs.write_code().unwrap();
+//! ```
+//!
+//! ## Manifest
+//!
+//! Outputs of constants removed from the build script are removed by
+//! `write_code`, so stale `include!`s fail to compile instead of using old
+//! values. The generated constants, with their type, size and a hash of
+//! their contents, are listed in `$OUT_DIR/cconst-manifest.txt`.
+//!
+//! ## Names
+//!
+//! Names of constants must be ASCII identifiers, as they are used both in the
+//! generated code and in file names. Keywords are emitted as raw identifiers,
+//! a constant `fn` is accessed as `r#fn()` but included as `cconst!(fn)`.
+//! Adding a name twice fails, unless replaced explicitly using
+//! `replace_const`, or removed first using `remove`:
+//!
+//! ```
+//! # use cconst::{CopyConsts, Error};
+//! let mut cs = CopyConsts::new();
+//! cs.add_const("fn", "u8", &1u8).unwrap();
+//!
+//! assert!(matches!(cs.add_const("my-const", "u8", &2u8), Err(Error::InvalidIdentifier(_))));
+//! assert!(matches!(cs.add_const("fn", "u8", &2u8), Err(Error::DuplicateName(_))));
+//! cs.replace_const("fn", "u8", &2u8).unwrap();
+//!
+//! assert!(cs.remove("fn").is_some());
+//! cs.add_str("fn", "now a string").unwrap();
+//!
+//! assert!(matches!(cs.group("my-grp"), Err(Error::InvalidIdentifier(_))));
+//! assert!(matches!(cs.group("fn"), Err(Error::InvalidIdentifier(_))));
+//! ```
+//!
+//! ## Errors
+//!
+//! Adding and writing constants fails with a `cconst::Error`, e.g. for types
+//! that cannot be named or values that cannot be stored for the target.
+//! Build scripts usually `unwrap` these, failing the build with the error's
+//! description.
+//!
 //! ## Caveats
 //!
 //! Due to the nature of the code generation used, the type supplied to the
 //! `add_const!`-macro should be fully qualified, i.e. start with a `::`. If
-//! not, it must be visible at the `include!` call site.
+//! not, it must be visible at the `include!` call site. `CopyConsts::add`
+//! avoids this by deriving a fully qualified path from
+//! `std::any::type_name`, failing for types that cannot be named outside the
+//! build script.
 //!
 //! While `Copy` indicates that the type can freely be copied, if any resources
 //! are held by the type outside of what the compiler knows, the type cannot be
-//! compile generated.
+//! compile generated. Implementing `ConstBytes` for such a type is unsound.
 //!
 //! Types that have the same names but are different will result in undefined
 //! behaviour with possibly catastrophic results. This will most likely occur
 //! when the `build_dependencies` and `dependencies` versions of a required
-//! crate differ.
+//! crate differ. To catch this, the generated code checks size, alignment and
+//! the `ConstBytes::FINGERPRINT` of the type at compile time. Derived
+//! implementations fingerprint the defining crate's name and version, the
+//! type's path and its fields, types implementing `ConstBytes` manually
+//! should provide a fingerprint using the functions in `cconst::fingerprint`.
+//! For this check, `cconst` must also be a regular dependency of the crate
+//! including the constants.
 //!
 //! ## TODO
 //!
@@ -74,89 +398,851 @@ macro_rules! cconst {
     ($fname:ident) => (concat!(env!("OUT_DIR"), "/cconst-", stringify!($fname), ".rs"))
 }
 
+/// Imports all constants written as a bundle, see `CopyConsts::bundle`
+#[macro_export]
+macro_rules! cconst_all {
+    () => (include!(concat!(env!("OUT_DIR"), "/cconst.rs"));)
+}
+
+/// Imports a group of constants as a module, see `CopyConsts::group`
+#[macro_export]
+macro_rules! cconst_group {
+    ($group:ident) => (
+        pub mod $group {
+            include!(concat!(env!("OUT_DIR"), "/cconst.", stringify!($group), ".rs"));
+        }
+    )
+}
+
+/// Describes the layout of a struct from a list of its fields.
+///
+/// Creates a `Layout` in which all bytes not covered by one of the listed
+/// fields are considered padding. Every field must implement `ConstBytes`,
+/// its own layout is nested at the field's offset.
+#[macro_export]
+macro_rules! layout {
+    ($stype:ty { $($field:tt),* $(,)* }) => ({
+        let mut layout = $crate::Layout::new(::std::mem::size_of::<$stype>());
+        $(
+            layout.nested(::std::mem::offset_of!($stype, $field),
+                          &$crate::layout::field_layout(|v: &$stype| &v.$field));
+        )*
+        layout
+    })
+}
+
 /// Creates a constant for inclusion using `cconst!`.
 ///
 /// This macro should be preferred over `CopyConsts::add_const`, as it provides
 /// additional type safety.
 #[macro_export]
 macro_rules! add_const {
-    ($cconsts:expr, $fname: expr, $ctype:ty, $val:expr) => (
+    ($cconsts:expr, $fname: expr, $ctype:ty, $val:expr) => ({
         let mat: $ctype = $val;
-        $cconsts.add_const($fname, stringify!($ctype), &mat);
-        )
+        $cconsts.add_const($fname, stringify!($ctype), &mat)
+    })
 }
 
-use std::{collections, env, fs, io};
-use std::io::Write;
-use std::mem::size_of;
+/// Creates a constant rendered as an expression for inclusion using `cconst!`.
+///
+/// Like `add_const!`, but uses `CopyConsts::add_expr`.
+#[macro_export]
+macro_rules! add_expr {
+    ($cconsts:expr, $fname: expr, $ctype:ty, $val:expr) => ({
+        let mat: $ctype = $val;
+        $cconsts.add_expr($fname, stringify!($ctype), &mat)
+    })
+}
 
-fn marshall_value<T: Copy>(val: &T) -> String {
-    let vptr = val as *const _ as *const u8;
+#[cfg(feature = "derive")]
+#[allow(unused_imports)]
+#[macro_use]
+extern crate cconst_derive;
 
-    let mut rexpr = String::new();
-    rexpr += "&[";
+mod bytes;
+mod constant;
+mod error;
+mod expr;
+pub mod fingerprint;
+mod ident;
+#[doc(hidden)]
+pub mod layout;
+mod manifest;
+mod map;
+mod sorted;
+mod table;
+mod target;
+mod typename;
 
-    for i in 0..size_of::<T>() {
-        rexpr.push_str(&format!("0x{:02X}, ", unsafe { *vptr.offset(i as isize) }));
-    }
+pub use bytes::ConstBytes;
+pub use constant::{Constant, Item};
+#[cfg(feature = "derive")]
+pub use cconst_derive::{ConstBytes, ToConstExpr};
+pub use expr::{ExprWriter, ToConstExpr};
+pub use layout::{FieldKind, Layout};
+pub use map::{Iter, Map};
+pub use sorted::{Set, SortedTable};
+pub use table::{Table, TableIndex};
+pub use error::Error;
+pub use typename::UnresolvedType;
 
-    rexpr += "]";
+use constant::{Shape, Value};
+use manifest::{Manifest, Session};
+use target::Target;
+use std::{collections, env, fs, path, process, slice};
+use std::any::type_name;
+use std::collections::hash_map::Entry;
+use std::hash::Hash;
+use std::mem::{align_of, size_of, size_of_val};
+use std::ops::{Bound, RangeBounds};
+
+/// Creates the byte image of `vals`, stored back to back.
+fn marshall_values<T: ConstBytes>(vals: &[T], layout: &Layout) -> Vec<u8> {
+    assert_eq!(layout.size(),
+               size_of::<T>(),
+               "layout size does not match size of value");
+
+    let mask = layout.data_mask();
+    let mut image = Vec::with_capacity(size_of_val(vals));
+
+    for val in vals {
+        let vptr = val as *const _ as *const u8;
+
+        image.extend(mask.iter().enumerate().map(|(i, &is_data)| {
+            // padding bytes are uninitialized and must not be read
+            if is_data { unsafe { *vptr.add(i) } } else { 0 }
+        }));
+    }
 
-    rexpr
+    image
 }
 
-fn create_constant_func<T: Copy>(fname: &str, typename: &str, val: &T) -> String {
-    let sval = marshall_value(val);
-
-    format!("#[inline]\nfn {}() -> &'static {} {{
-    const BUF: &[u8] = {};
-    unsafe {{ &*(BUF.as_ptr() as *const {}) }}
-}}\n",
-            fname,
-            typename,
-            sval,
-            typename)
+fn bytes_value<T: ConstBytes>(vals: &[T], shape: Shape) -> Value {
+    let layout = T::layout();
+
+    Value::Bytes {
+        image: marshall_values(vals, &layout),
+        layout,
+        align: align_of::<T>(),
+        fingerprint: T::FINGERPRINT,
+        type_name: type_name::<T>(),
+        shape,
+    }
 }
 
 /// Manage `build.rs` constructed constants
-pub struct CopyConsts(collections::HashMap<String, String>);
+pub struct CopyConsts {
+    constants: collections::HashMap<String, Constant>,
+    groups: collections::BTreeMap<String, CopyConsts>,
+    /// Start of the names of all files written, relative to `OUT_DIR`
+    prefix: String,
+    /// Threshold set on this instance, groups otherwise use their parent's
+    blob_threshold: Option<usize>,
+    bundle: bool,
+    grouped: bool,
+    /// Files and environment variables the constants are computed from
+    file_dependencies: Vec<path::PathBuf>,
+    env_dependencies: Vec<String>,
+}
+
+impl Default for CopyConsts {
+    fn default() -> CopyConsts {
+        CopyConsts::new()
+    }
+}
+
+
+/// Joins the code of constants grouped by module path into nested modules.
+///
+/// Module paths are sorted, so every module directly follows its parent.
+fn render_bundle(modules: &collections::BTreeMap<&[String], collections::BTreeMap<&String, String>>)
+                 -> String {
+    let mut code = String::new();
+    let mut open: &[String] = &[];
+
+    for (&path, constants) in modules {
+        while !path.starts_with(open) {
+            code += "}\n";
+            open = &open[..open.len() - 1];
+        }
+
+        for segment in &path[open.len()..] {
+            code += &format!("pub mod {} {{\n", ident::escape(segment));
+        }
+        open = path;
+
+        for constant in constants.values() {
+            code += constant;
+        }
+    }
+
+    for _ in open {
+        code += "}\n";
+    }
+
+    code
+}
+
+/// Writes `contents` to `path`, unless it already holds them.
+///
+/// Leaving unchanged files untouched keeps their modification time, which
+/// would otherwise cause cargo to recompile the crate including them. New
+/// contents are written to a temporary file first and renamed into place, so
+/// an interrupted build never leaves a truncated file behind.
+///
+/// A file marked stale by an earlier `write_code` call of the build script is
+/// renamed back into place if it holds `contents`, see `manifest`.
+fn write_output(path: &str, contents: &[u8]) -> Result<(), Error> {
+    match fs::read(path) {
+        Ok(ref existing) if existing[..] == *contents => return Ok(()),
+        _ => (),
+    }
+
+    let stale_path = path.to_owned() + manifest::STALE_SUFFIX;
+    match fs::read(&stale_path) {
+        Ok(ref stale) if stale[..] == *contents => {
+            return fs::rename(&stale_path, path).map_err(|e| Error::io(path, e));
+        }
+        Ok(_) => {
+            let _ = fs::remove_file(&stale_path);
+        }
+        Err(_) => (),
+    }
+
+    let tmp_path = format!("{}.{}.tmp", path, process::id());
+    fs::write(&tmp_path, contents)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp_path);
+            Error::io(path, e)
+        })
+}
 
+/// Size of byte images above which they are stored in side files by default
+const DEFAULT_BLOB_THRESHOLD: usize = 64 * 1024;
 
-fn build_output_path(fname: &str) -> Result<String, env::VarError> {
-    Ok(env::var("OUT_DIR")? + "/cconst-" + fname + ".rs")
+fn out_dir() -> Result<String, Error> {
+    env::var("OUT_DIR").map_err(|_| Error::MissingOutDir)
 }
 
 impl CopyConsts {
     /// Create new set of compile time functions
     pub fn new() -> CopyConsts {
-        CopyConsts(collections::HashMap::new())
+        CopyConsts {
+            constants: collections::HashMap::new(),
+            groups: collections::BTreeMap::new(),
+            prefix: "cconst".to_owned(),
+            blob_threshold: None,
+            bundle: false,
+            grouped: false,
+            file_dependencies: Vec::new(),
+            env_dependencies: Vec::new(),
+        }
+    }
+
+    /// Register a file the constants are computed from.
+    ///
+    /// `write_code` tells cargo to rerun the build script when the file
+    /// changes. Once any dependency is registered, cargo no longer reruns the
+    /// build script for changes to other files of the package, so all inputs
+    /// should be listed.
+    pub fn depends_on_file<P: AsRef<path::Path>>(&mut self, path: P) -> &mut CopyConsts {
+        self.file_dependencies.push(path.as_ref().to_owned());
+        self
+    }
+
+    /// Register an environment variable the constants are computed from.
+    ///
+    /// `write_code` tells cargo to rerun the build script when the variable
+    /// changes.
+    pub fn depends_on_env(&mut self, var: &str) -> &mut CopyConsts {
+        self.env_dependencies.push(var.to_owned());
+        self
+    }
+
+    /// Get a group of constants, creating it if necessary.
+    ///
+    /// Constants added to the group live in a separate namespace. The group is
+    /// written to its own file, included as a module named `name` using
+    /// `cconst_group!(name)`, or as part of the bundle if the parent is
+    /// bundled. Groups can be nested and always bundle their constants.
+    ///
+    /// Fails if `name` is not an identifier, or is a keyword.
+    pub fn group(&mut self, name: &str) -> Result<&mut CopyConsts, Error> {
+        let name = ident::validate_group(name)?;
+        let prefix = format!("{}.{}", self.prefix, name);
+
+        Ok(self.groups.entry(name.to_owned()).or_insert_with(|| {
+            CopyConsts {
+                prefix,
+                bundle: true,
+                grouped: true,
+                ..CopyConsts::new()
+            }
+        }))
+    }
+
+    /// Write all constants into a single module.
+    ///
+    /// Instead of one file per constant, `write_code` writes `cconst.rs`,
+    /// included at once using `cconst_all!()`. Constants can be placed in
+    /// submodules of it using `Constant::module`.
+    pub fn bundle(&mut self) -> &mut CopyConsts {
+        self.bundle = true;
+        self
+    }
+
+    /// Set the size above which byte images are stored in side files.
+    ///
+    /// Larger images are written to a `.bin` file next to the generated code
+    /// and included using `include_bytes!`, which compiles considerably faster
+    /// than a literal. Defaults to 64 KiB.
+    ///
+    /// Groups use the threshold of their parent at the time of `write_code`,
+    /// unless set on the group itself.
+    pub fn blob_threshold(&mut self, bytes: usize) -> &mut CopyConsts {
+        self.blob_threshold = Some(bytes);
+        self
     }
 
     /// Add constant
     ///
     /// Adds a value to be stored as a compile time constant, with an internal
-    /// name of `fname`.
+    /// name of `fname`. Fails if `fname` is not a valid name or already taken,
+    /// see the crate documentation on names.
     ///
     /// `typename` is required to output generated code, but not checked. For
     /// this reason using the `add_const!` macro instead of this function
     /// should be preferred.
-    pub fn add_const<T: Copy>(&mut self, fname: &str, typename: &str, val: &T) {
-        self.0
-            .insert(fname.to_owned(), create_constant_func(fname, typename, val));
+    pub fn add_const<T: ConstBytes>(&mut self,
+                                    fname: &str,
+                                    typename: &str,
+                                    val: &T)
+                                    -> Result<&mut Constant, Error> {
+        let value = bytes_value(slice::from_ref(val), Shape::Value);
+        self.insert(fname, Constant::new(typename, value))
+    }
+
+    /// Replace constant
+    ///
+    /// Like `add_const`, but overwrites a constant previously added as
+    /// `fname` instead of failing. Settings like `Constant::item` are not
+    /// carried over. Constants of other kinds can be replaced by calling
+    /// `remove` before adding them again.
+    pub fn replace_const<T: ConstBytes>(&mut self,
+                                        fname: &str,
+                                        typename: &str,
+                                        val: &T)
+                                        -> Result<&mut Constant, Error> {
+        self.constants.remove(ident::validate(fname)?);
+        self.add_const(fname, typename, val)
+    }
+
+    /// Remove constant
+    ///
+    /// Returns the constant previously added as `fname`, if any, making the
+    /// name available again.
+    pub fn remove(&mut self, fname: &str) -> Option<Constant> {
+        let fname = ident::validate(fname).ok()?;
+        self.constants.remove(fname)
+    }
+
+    /// Add constant, naming its type automatically
+    ///
+    /// Like `add_const`, but the path of `T` is derived from
+    /// `std::any::type_name` instead of being passed in. Paths of standard
+    /// library types are rewritten to their public re-exports.
+    ///
+    /// Fails for types whose path obviously cannot be named at the `include!`
+    /// site, e.g. types defined inside the build script itself. Other paths
+    /// are not checked, a type in a private module of another crate only fails
+    /// to compile where the constant is included.
+    pub fn add<T: ConstBytes>(&mut self,
+                              fname: &str,
+                              val: &T)
+                              -> Result<&mut Constant, Error> {
+        let typename = typename::type_path::<T>()?;
+        self.add_const(fname, &typename, val)
+    }
+
+    /// Add slice constant
+    ///
+    /// Stores `vals` in an aligned array, accessed as a `&'static [T]`. The
+    /// element type is named automatically, as in `add`.
+    pub fn add_slice<T: ConstBytes>(&mut self,
+                                    fname: &str,
+                                    vals: &[T])
+                                    -> Result<&mut Constant, Error> {
+        let typename = typename::type_path::<T>()?;
+        let value = bytes_value(vals, Shape::Slice(vals.len()));
+        self.insert(fname, Constant::new(&typename, value))
+    }
+
+    /// Add table constant
+    ///
+    /// Evaluates `f` for each index in `range`, storing the results in an
+    /// aligned array accessed as a `&'static Table<I, T>`. The table is
+    /// indexed by the integer type of the range, starting at its first index.
+    /// Types are named automatically, as in `add`.
+    ///
+    /// Fails if `range` does not include its start, e.g. is unbounded.
+    pub fn add_table<I, T, R, F>(&mut self,
+                                 fname: &str,
+                                 range: R,
+                                 f: F)
+                                 -> Result<&mut Constant, Error>
+        where I: TableIndex + ToConstExpr,
+              T: ConstBytes,
+              R: RangeBounds<I> + IntoIterator<Item = I>,
+              F: FnMut(I) -> T
+    {
+        let typename = typename::type_path::<T>()?;
+        let index_type = typename::type_path::<I>()?;
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            _ => return Err(Error::InvalidRange(fname.to_owned())),
+        };
+
+        let vals: Vec<T> = range.into_iter().map(f).collect();
+        let shape = Shape::Table {
+            len: vals.len(),
+            index_type,
+            start: ExprWriter::render(&start),
+        };
+        self.insert(fname, Constant::new(&typename, bytes_value(&vals, shape)))
+    }
+
+    /// Add constant rendered as an expression
+    ///
+    /// Instead of a byte image, the value is stored as Rust source code
+    /// constructing it. Like with `add_const`, `typename` is not checked
+    /// here, but by the compiler at the `include!` site.
+    pub fn add_expr<T: ToConstExpr + ?Sized>(&mut self,
+                                             fname: &str,
+                                             typename: &str,
+                                             val: &T)
+                                             -> Result<&mut Constant, Error> {
+        let value = Value::Expr(ExprWriter::render(val));
+        self.insert(fname, Constant::new(typename, value))
+    }
+
+    /// Add map constant
+    ///
+    /// Lays out `entries` using a perfect hash function, accessed as a
+    /// `&'static Map<K, V>`. Keys and values are rendered as expressions,
+    /// `key_type` and `value_type` name their types at the `include!` site.
+    /// If a key occurs more than once, the last value is kept.
+    pub fn add_map<K, V, I>(&mut self,
+                            fname: &str,
+                            key_type: &str,
+                            value_type: &str,
+                            entries: I)
+                            -> Result<&mut Constant, Error>
+        where K: ToConstExpr + Hash + Eq,
+              V: ToConstExpr,
+              I: IntoIterator<Item = (K, V)>
+    {
+        let typename = format!("::cconst::Map<{}, {}>", key_type, value_type);
+        let value = Value::Expr(map::render(entries));
+        self.insert(fname, Constant::new(&typename, value))
+    }
+
+    /// Add set constant
+    ///
+    /// Sorts and deduplicates `keys`, accessed as a `&'static Set<K>`. Keys are
+    /// rendered as expressions, `key_type` names their type at the `include!`
+    /// site.
+    pub fn add_set<K, I>(&mut self,
+                         fname: &str,
+                         key_type: &str,
+                         keys: I)
+                         -> Result<&mut Constant, Error>
+        where K: ToConstExpr + Ord,
+              I: IntoIterator<Item = K>
+    {
+        let typename = format!("::cconst::Set<{}>", key_type);
+        let value = Value::Expr(sorted::render_set(keys));
+        self.insert(fname, Constant::new(&typename, value))
+    }
+
+    /// Add sorted lookup table constant
+    ///
+    /// Sorts `entries` by key, accessed as a `&'static SortedTable<K, V>`. If
+    /// a key occurs more than once, the last value is kept.
+    pub fn add_sorted_table<K, V, I>(&mut self,
+                                     fname: &str,
+                                     key_type: &str,
+                                     value_type: &str,
+                                     entries: I)
+                                     -> Result<&mut Constant, Error>
+        where K: ToConstExpr + Ord,
+              V: ToConstExpr,
+              I: IntoIterator<Item = (K, V)>
+    {
+        let typename = format!("::cconst::SortedTable<{}, {}>", key_type, value_type);
+        let value = Value::Expr(sorted::render_table(entries));
+        self.insert(fname, Constant::new(&typename, value))
+    }
+
+    /// Add string constant
+    ///
+    /// The string is stored as a literal, accessed as a `&'static str`.
+    pub fn add_str(&mut self, fname: &str, val: &str) -> Result<&mut Constant, Error> {
+        let value = Value::Literal(format!("{:?}", val));
+        self.insert(fname, Constant::new("str", value))
+    }
+
+    /// Add byte string constant
+    ///
+    /// The bytes are stored as a `b"..."` literal, accessed as a
+    /// `&'static [u8]`.
+    pub fn add_bytes(&mut self, fname: &str, val: &[u8]) -> Result<&mut Constant, Error> {
+        let value = Value::Literal(constant::byte_string_literal(val));
+        self.insert(fname, Constant::new("[u8]", value))
+    }
+
+    fn insert(&mut self, fname: &str, mut constant: Constant) -> Result<&mut Constant, Error> {
+        let fname = ident::validate(fname)?;
+        constant.set_grouped(self.grouped);
+
+        match self.constants.entry(fname.to_owned()) {
+            Entry::Occupied(_) => Err(Error::DuplicateName(fname.to_owned())),
+            Entry::Vacant(entry) => Ok(entry.insert(constant)),
+        }
     }
 
     /// Write out code for compile-time constant generation.
-    pub fn write_code(&self) -> io::Result<()> {
-        for (fname, buf) in &self.0 {
-            let output_path =
-                build_output_path(fname)
-                    .map_err(|_| io::Error::new(io::ErrorKind::Other, "missing OUT_PATH"))?;
+    ///
+    /// Byte images are converted for the target being compiled for, as
+    /// reported by cargo. Fails with `Error::UnsupportedLayout` if a value
+    /// cannot be represented on the target.
+    ///
+    /// Files whose contents are unchanged are left untouched, avoiding
+    /// needless recompilation. The only output on stdout are
+    /// `cargo:rerun-if-*` directives for the registered dependencies.
+    ///
+    /// All generated constants and files are listed in
+    /// `$OUT_DIR/cconst-manifest.txt`. Files of the previous run that are no
+    /// longer generated are removed. Outputs of earlier calls within the same
+    /// build script are kept, so several `CopyConsts`, or a group written on
+    /// its own, can be written one after another.
+    pub fn write_code(&self) -> Result<(), Error> {
+        let target = Target::from_env();
+        let out_dir = out_dir()?;
+        self.print_dependencies();
+
+        manifest::with_session(&out_dir, |session| self.write_session(session, &target))
+    }
+
+    /// Write all outputs, recording them in `session`.
+    fn write_session(&self, session: &mut Session, target: &Target) -> Result<(), Error> {
+        let out_dir = session.out_dir().to_owned();
+        let mut manifest = Manifest::new();
+        let blob_threshold = self.blob_threshold.unwrap_or(DEFAULT_BLOB_THRESHOLD);
+
+        if self.bundle {
+            self.write_bundle(&out_dir, target, blob_threshold, &mut manifest)?;
+        } else {
+            for (fname, constant) in &self.constants {
+                let code = self.render(fname,
+                                       constant,
+                                       &out_dir,
+                                       target,
+                                       blob_threshold,
+                                       &mut manifest)?;
+                let file_name = self.prefix.clone() + "-" + fname + ".rs";
+
+                write_output(&(out_dir.clone() + "/" + &file_name), code.as_bytes())?;
+                manifest.add_file(file_name);
+            }
+
+            for group in self.groups.values() {
+                group.write_bundle(&out_dir, target, blob_threshold, &mut manifest)?;
+            }
+        }
+
+        session.record(manifest)
+    }
+
+    /// Print `rerun-if` directives for the dependencies of all groups.
+    fn print_dependencies(&self) {
+        for file in &self.file_dependencies {
+            println!("cargo:rerun-if-changed={}", file.display());
+        }
+
+        for var in &self.env_dependencies {
+            println!("cargo:rerun-if-env-changed={}", var);
+        }
+
+        for group in self.groups.values() {
+            group.print_dependencies();
+        }
+    }
 
-            write!(io::stdout(), "OUTPUT PATH {:?}", output_path).unwrap();
-            let mut fp = fs::File::create(output_path)?;
-            fp.write_all(buf.as_bytes())?;
+    /// Write all constants and groups into a single module.
+    ///
+    /// `blob_threshold` is the threshold inherited from the parent.
+    fn write_bundle(&self,
+                    out_dir: &str,
+                    target: &Target,
+                    blob_threshold: usize,
+                    manifest: &mut Manifest)
+                    -> Result<(), Error> {
+        let blob_threshold = self.blob_threshold.unwrap_or(blob_threshold);
+        let mut modules = collections::BTreeMap::new();
+
+        for (fname, constant) in &self.constants {
+            if let Some(module) = constant.module_path().first() {
+                if self.groups.contains_key(module) {
+                    return Err(Error::DuplicateModule(module.clone()));
+                }
+            }
+
+            modules.entry(constant.module_path())
+                .or_insert_with(collections::BTreeMap::new)
+                .insert(fname,
+                        self.render(fname, constant, out_dir, target, blob_threshold, manifest)?);
+        }
+
+        let mut code = render_bundle(&modules);
+
+        for (name, group) in &self.groups {
+            code += &format!("pub mod {} {{\n    include!(concat!(env!(\"OUT_DIR\"), \"/{}.rs\"));\n}}\n",
+                             name,
+                             group.prefix);
+            group.write_bundle(out_dir, target, blob_threshold, manifest)?;
         }
 
+        let file_name = self.prefix.clone() + ".rs";
+
+        write_output(&(out_dir.to_owned() + "/" + &file_name), code.as_bytes())?;
+        manifest.add_file(file_name);
         Ok(())
     }
+
+    /// Generate code for a constant, writing its side file if needed.
+    fn render(&self,
+              fname: &str,
+              constant: &Constant,
+              out_dir: &str,
+              target: &Target,
+              blob_threshold: usize,
+              manifest: &mut Manifest)
+              -> Result<String, Error> {
+        let rendered = constant.render(&self.prefix, fname, target, blob_threshold)
+            .map_err(|reason| {
+                Error::UnsupportedLayout {
+                    name: fname.to_owned(),
+                    target: target.triple().to_owned(),
+                    reason,
+                }
+            })?;
+
+        if let Some(ref blob) = rendered.blob {
+            let file_name = constant.blob_name(&self.prefix, fname);
+
+            write_output(&(out_dir.to_owned() + "/" + &file_name), blob)?;
+            manifest.add_file(file_name);
+        }
+
+        manifest.add_constant(self.qualified_name(fname, constant),
+                              constant.typename(),
+                              rendered.size,
+                              &rendered.code,
+                              rendered.blob.as_ref().map(|blob| &blob[..]));
+
+        Ok(rendered.code)
+    }
+
+    /// Path of a constant relative to the including module, as listed in the
+    /// manifest
+    fn qualified_name(&self, fname: &str, constant: &Constant) -> String {
+        let mut segments: Vec<&str> = self.prefix.split('.').skip(1).collect();
+        if self.bundle {
+            segments.extend(constant.module_path().iter().map(|segment| &segment[..]));
+        }
+        segments.push(fname);
+        segments.join("::")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    /// Empty directory for a test, deleted when dropped
+    pub(crate) struct TempDir(pub(crate) String);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> TempDir {
+            let path = env::temp_dir().join(format!("cconst-test-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path.to_str().unwrap().to_owned())
+        }
+
+        pub(crate) fn path(&self, file_name: &str) -> String {
+            format!("{}/{}", self.0, file_name)
+        }
+
+        pub(crate) fn files(&self) -> Vec<String> {
+            let mut files: Vec<_> = fs::read_dir(&self.0)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    pub(crate) fn set_modified(path: &str, time: SystemTime) {
+        fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+    }
+
+    fn modified(path: &str) -> SystemTime {
+        fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[cfg(unix)]
+    fn inode(path: &str) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        fs::metadata(path).unwrap().ino()
+    }
+
+    fn assert_no_temporary_files(dir: &TempDir) {
+        let files = dir.files();
+        assert!(files.iter().all(|name| !name.ends_with(".tmp")), "{:?}", files);
+    }
+
+    #[test]
+    fn write_output_skips_identical_contents() {
+        let dir = TempDir::new("write-same");
+        let path = dir.path("out.rs");
+        write_output(&path, b"fn a() {}").unwrap();
+
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        set_modified(&path, old);
+        #[cfg(unix)]
+        let ino = inode(&path);
+
+        write_output(&path, b"fn a() {}").unwrap();
+        assert_eq!(modified(&path), old);
+        #[cfg(unix)]
+        assert_eq!(inode(&path), ino);
+        assert_no_temporary_files(&dir);
+    }
+
+    #[test]
+    fn write_output_replaces_changed_contents() {
+        let dir = TempDir::new("write-changed");
+        let path = dir.path("out.rs");
+        write_output(&path, b"fn a() {}").unwrap();
+
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        set_modified(&path, old);
+
+        write_output(&path, b"fn b() {}").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"fn b() {}");
+        assert!(modified(&path) > old);
+        assert_eq!(dir.files(), ["out.rs"]);
+    }
+
+    #[test]
+    fn write_output_cleans_up_after_failure() {
+        let dir = TempDir::new("write-failed");
+        let path = dir.path("out.rs");
+        // renaming a file onto a non-empty directory fails
+        fs::create_dir(&path).unwrap();
+        fs::write(dir.path("out.rs/keep"), b"").unwrap();
+
+        match write_output(&path, b"fn a() {}") {
+            Err(Error::Io { path: ref error_path, .. }) => {
+                assert_eq!(error_path.to_str(), Some(&path[..]))
+            }
+            result => panic!("unexpected result {:?}", result),
+        }
+        assert_no_temporary_files(&dir);
+    }
+
+    #[test]
+    fn keyword_names_are_raw_identifiers_in_code_only() {
+        let out_dir = TempDir::new("keyword");
+        let mut session = Session::open(&out_dir.0).unwrap();
+
+        let mut cs = CopyConsts::new();
+        cs.add_str("r#fn", "x").unwrap();
+        cs.write_session(&mut session, &Target::from_env()).unwrap();
+
+        assert_eq!(out_dir.files(), ["cconst-fn.rs", "cconst-manifest.txt"]);
+        let code = fs::read_to_string(out_dir.path("cconst-fn.rs")).unwrap();
+        assert!(code.contains("fn r#fn()"), "{}", code);
+    }
+
+    #[test]
+    fn bundle_modules() {
+        let paths: Vec<Vec<String>> = [&[][..], &["a", "b"], &["a", "c"], &["c"], &["fn"]]
+            .iter()
+            .map(|path| path.iter().map(|segment| segment.to_string()).collect())
+            .collect();
+        let names: Vec<String> = ["v", "w", "x", "y", "z"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+
+        let mut modules = collections::BTreeMap::new();
+        for (path, name) in paths.iter().zip(&names) {
+            modules.entry(&path[..])
+                .or_insert_with(collections::BTreeMap::new)
+                .insert(name, format!("{};\n", name));
+        }
+
+        assert_eq!(render_bundle(&modules),
+                   "v;\n\
+                    pub mod a {\npub mod b {\nw;\n}\npub mod c {\nx;\n}\n}\n\
+                    pub mod c {\ny;\n}\n\
+                    pub mod r#fn {\nz;\n}\n");
+        assert_eq!(render_bundle(&collections::BTreeMap::new()), "");
+    }
+
+    /// A build script writing two `CopyConsts` and a group on its own
+    fn run_build_script(out_dir: &TempDir) {
+        let target = Target::from_env();
+        let mut session = Session::open(&out_dir.0).unwrap();
+
+        let mut a = CopyConsts::new();
+        a.add("aa", &1u32).unwrap();
+        a.write_session(&mut session, &target).unwrap();
+
+        let mut b = CopyConsts::new();
+        b.add("bb", &2u32).unwrap();
+        b.group("g").unwrap().add("cc", &3u32).unwrap();
+        b.write_session(&mut session, &target).unwrap();
+        b.group("g").unwrap().write_session(&mut session, &target).unwrap();
+    }
+
+    #[test]
+    fn rerun_keeps_outputs_of_several_write_code_calls() {
+        let out_dir = TempDir::new("rerun");
+        let outputs = ["cconst-aa.rs", "cconst-bb.rs", "cconst.g.rs"];
+
+        run_build_script(&out_dir);
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        for file_name in &outputs {
+            set_modified(&out_dir.path(file_name), old);
+        }
+
+        run_build_script(&out_dir);
+        for file_name in &outputs {
+            assert_eq!(modified(&out_dir.path(file_name)), old, "{}", file_name);
+        }
+        assert_eq!(out_dir.files(),
+                   ["cconst-aa.rs", "cconst-bb.rs", "cconst-manifest.txt", "cconst.g.rs"]);
+    }
 }
@@ -0,0 +1,308 @@
+//! Record of the generated outputs
+//!
+//! `CopyConsts::write_code` lists every constant and every file it writes in
+//! `$OUT_DIR/cconst-manifest.txt`. Files listed by the previous run but not
+//! written by the current one belong to removed constants, so including them
+//! must fail instead of silently using stale data.
+//!
+//! A build script may call `write_code` several times, e.g. on separate
+//! `CopyConsts`, each seeing only part of the outputs. Stale files are
+//! therefore renamed to `<name>.stale` rather than deleted: a later call of
+//! the same process writing identical contents renames them back, keeping
+//! their modification time. Files still stale at the end of a run are deleted
+//! by the next one.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::{fs, io};
+
+use error::Error;
+use fingerprint::{self, FNV_OFFSET};
+use write_output;
+
+/// Name of the manifest, relative to `OUT_DIR`
+const FILE_NAME: &str = "cconst-manifest.txt";
+
+/// Appended to the names of outputs not written by the current run
+pub(crate) const STALE_SUFFIX: &str = ".stale";
+
+/// Session of this process
+static SESSION: Mutex<Option<Session>> = Mutex::new(None);
+
+/// Run `f` with the session of this process for `out_dir`, opening it on
+/// first use.
+pub(crate) fn with_session<T, F>(out_dir: &str, f: F) -> Result<T, Error>
+    where F: FnOnce(&mut Session) -> Result<T, Error>
+{
+    // a panic while holding the lock leaves the session consistent
+    let mut session = SESSION.lock().unwrap_or_else(|e| e.into_inner());
+
+    match *session {
+        Some(ref session) if session.out_dir == out_dir => (),
+        _ => *session = Some(Session::open(out_dir)?),
+    }
+
+    f(session.as_mut().expect("session was just opened"))
+}
+
+/// Outputs of all `write_code` calls of a run
+pub(crate) struct Session {
+    out_dir: String,
+    /// Files listed by the manifest of the previous run
+    previous: BTreeSet<String>,
+    written: Manifest,
+}
+
+impl Session {
+    /// Start a run writing to `out_dir`, deleting files left stale by the
+    /// previous one.
+    pub(crate) fn open(out_dir: &str) -> Result<Session, Error> {
+        let path = format!("{}/{}", out_dir, FILE_NAME);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(Error::io(path, e)),
+        };
+
+        let mut previous = BTreeSet::new();
+        for line in contents.lines() {
+            let mut fields = line.splitn(2, '\t');
+
+            match (fields.next(), fields.next().filter(|name| is_file_name(name))) {
+                (Some("file"), Some(name)) => {
+                    previous.insert(name.to_owned());
+                }
+                (Some("stale"), Some(name)) => {
+                    remove_file(&format!("{}/{}{}", out_dir, name, STALE_SUFFIX))?
+                }
+                _ => (),
+            }
+        }
+
+        Ok(Session {
+            out_dir: out_dir.to_owned(),
+            previous,
+            written: Manifest::new(),
+        })
+    }
+
+    pub(crate) fn out_dir(&self) -> &str {
+        &self.out_dir
+    }
+
+    /// Add the outputs of a `write_code` call to those of earlier calls,
+    /// marking outputs of the previous run as stale and writing the combined
+    /// manifest.
+    pub(crate) fn record(&mut self, manifest: Manifest) -> Result<(), Error> {
+        self.written.entries.extend(manifest.entries);
+        self.written.files.extend(manifest.files);
+
+        for name in self.stale() {
+            let path = format!("{}/{}", self.out_dir, name);
+
+            match fs::rename(&path, path.clone() + STALE_SUFFIX) {
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                result => result.map_err(|e| Error::io(path, e))?,
+            }
+        }
+
+        write_output(&format!("{}/{}", self.out_dir, FILE_NAME),
+                     self.render().as_bytes())
+    }
+
+    /// Files of the previous run not written by this one, so far
+    fn stale(&self) -> Vec<&String> {
+        self.previous.difference(&self.written.files).collect()
+    }
+
+    /// Contents of the manifest file
+    ///
+    /// One line per constant (`const`, name, type, size, hash), per file
+    /// (`file`, name) and per stale file (`stale`, name without suffix),
+    /// separated by tabs and sorted.
+    fn render(&self) -> String {
+        let mut out = String::from("# generated by cconst, do not edit\n");
+        for (name, entry) in &self.written.entries {
+            writeln!(out,
+                     "const\t{}\t{}\t{}\t{:016x}",
+                     name,
+                     entry.typename,
+                     entry.size,
+                     entry.hash)
+                .expect("writing to a string cannot fail");
+        }
+        for file_name in &self.written.files {
+            writeln!(out, "file\t{}", file_name).expect("writing to a string cannot fail");
+        }
+        for file_name in self.stale() {
+            writeln!(out, "stale\t{}", file_name).expect("writing to a string cannot fail");
+        }
+
+        out
+    }
+}
+
+/// Whether a name read from the manifest refers to a file directly inside
+/// `OUT_DIR`, so a corrupt manifest never touches files elsewhere
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/') && !name.contains('\\')
+}
+
+fn remove_file(path: &str) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.map_err(|e| Error::io(path, e)),
+    }
+}
+
+struct Entry {
+    typename: String,
+    size: usize,
+    hash: u64,
+}
+
+pub(crate) struct Manifest {
+    /// Constants by their qualified name
+    entries: BTreeMap<String, Entry>,
+    files: BTreeSet<String>,
+}
+
+impl Manifest {
+    pub(crate) fn new() -> Manifest {
+        Manifest {
+            entries: BTreeMap::new(),
+            files: BTreeSet::new(),
+        }
+    }
+
+    /// Record a constant, `size` being the length of its data in bytes.
+    ///
+    /// The hash covers the generated code and side file, it changes whenever
+    /// the output does.
+    pub(crate) fn add_constant(&mut self,
+                               name: String,
+                               typename: &str,
+                               size: usize,
+                               code: &str,
+                               blob: Option<&[u8]>) {
+        let hash = fingerprint::extend(FNV_OFFSET, code.as_bytes());
+
+        self.entries.insert(name,
+                            Entry {
+                                typename: typename.to_owned(),
+                                size,
+                                hash: fingerprint::extend(hash, blob.unwrap_or(&[])),
+                            });
+    }
+
+    /// Record a file written to `OUT_DIR`.
+    pub(crate) fn add_file(&mut self, file_name: String) {
+        self.files.insert(file_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use tests::TempDir;
+
+    fn touch(path: &str) {
+        fs::write(path, b"x").unwrap();
+    }
+
+    fn record(out_dir: &TempDir, file_names: &[&str]) -> Session {
+        let mut session = Session::open(&out_dir.0).unwrap();
+        let mut manifest = Manifest::new();
+
+        for file_name in file_names {
+            touch(&out_dir.path(file_name));
+            manifest.add_file(file_name.to_string());
+        }
+
+        session.record(manifest).unwrap();
+        session
+    }
+
+    #[test]
+    fn removed_outputs_become_stale_then_deleted() {
+        let out_dir = TempDir::new("manifest-removed");
+
+        record(&out_dir, &["a.rs", "b.rs", "b.bin"]);
+        record(&out_dir, &["a.rs"]);
+        assert_eq!(out_dir.files(),
+                   ["a.rs", "b.bin.stale", "b.rs.stale", "cconst-manifest.txt"]);
+        let manifest = fs::read_to_string(out_dir.path(FILE_NAME)).unwrap();
+        assert!(manifest.contains("stale\tb.rs\n"), "{}", manifest);
+
+        let session = Session::open(&out_dir.0).unwrap();
+        assert_eq!(out_dir.files(), ["a.rs", "cconst-manifest.txt"]);
+        assert!(session.previous.contains("a.rs"));
+    }
+
+    #[test]
+    fn lists_constants_and_files() {
+        let out_dir = TempDir::new("manifest-render");
+        let mut session = Session::open(&out_dir.0).unwrap();
+        let mut manifest = Manifest::new();
+        manifest.add_constant("g::x".to_owned(), "u32", 4, "code", None);
+        manifest.add_file("cconst.g.rs".to_owned());
+        session.record(manifest).unwrap();
+
+        let contents = fs::read_to_string(out_dir.path(FILE_NAME)).unwrap();
+        let hash = fingerprint::extend(FNV_OFFSET, b"code");
+        assert_eq!(contents,
+                   format!("# generated by cconst, do not edit\n\
+                            const\tg::x\tu32\t4\t{:016x}\n\
+                            file\tcconst.g.rs\n",
+                           hash));
+    }
+
+    #[test]
+    fn ignores_paths_outside_out_dir() {
+        let out_dir = TempDir::new("manifest-paths");
+        let outside = TempDir::new("manifest-paths-outside");
+        touch(&outside.path("victim"));
+        touch(&outside.path("victim.stale"));
+
+        let outside_name = Path::new(&outside.0).file_name().unwrap().to_str().unwrap();
+        let relative = format!("../{}/victim", outside_name);
+        fs::write(out_dir.path(FILE_NAME),
+                  format!("file\t{}\nfile\t{}\nfile\tsub\\victim\nfile\t..\nfile\t\n\
+                           stale\t{}\nstale\t{}\n",
+                          relative,
+                          outside.path("victim"),
+                          relative,
+                          outside.path("victim")))
+            .unwrap();
+
+        let mut session = Session::open(&out_dir.0).unwrap();
+        assert!(session.previous.is_empty());
+        session.record(Manifest::new()).unwrap();
+
+        assert_eq!(outside.files(), ["victim", "victim.stale"]);
+    }
+
+    #[test]
+    fn missing_manifest() {
+        let out_dir = TempDir::new("manifest-missing");
+        let session = Session::open(&out_dir.0).unwrap();
+        assert!(session.previous.is_empty());
+    }
+
+    #[test]
+    fn unreadable_manifest() {
+        let out_dir = TempDir::new("manifest-unreadable");
+        fs::write(out_dir.path(FILE_NAME), b"file\t\xff\n").unwrap();
+
+        match Session::open(&out_dir.0) {
+            Err(Error::Io { ref path, .. }) => assert!(path.ends_with(FILE_NAME)),
+            _ => panic!("invalid manifest accepted"),
+        }
+
+        fs::remove_file(out_dir.path(FILE_NAME)).unwrap();
+        fs::create_dir(out_dir.path(FILE_NAME)).unwrap();
+        assert!(Session::open(&out_dir.0).is_err());
+    }
+}
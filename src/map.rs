@@ -0,0 +1,433 @@
+//! Perfect hash maps
+//!
+//! Maps added through `CopyConsts::add_map` are laid out by the build script
+//! using a perfect hash function (hash and displace), so a lookup at runtime
+//! probes a single slot. Keys are hashed independently of the platform, the
+//! build script and the compiled crate always agree on the layout.
+
+use std::borrow::Borrow;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::{fmt, slice};
+
+use expr::{ExprWriter, ToConstExpr};
+use fingerprint::{self, FNV_OFFSET};
+
+/// Average number of keys sharing a displacement
+const LAMBDA: usize = 5;
+
+/// Immutable map generated by `CopyConsts::add_map`
+///
+/// Iteration order is the order of the hash table, not the order the entries
+/// were added in.
+pub struct Map<K: 'static, V: 'static> {
+    #[doc(hidden)]
+    pub seed: u64,
+    #[doc(hidden)]
+    pub disps: &'static [(u32, u32)],
+    #[doc(hidden)]
+    pub entries: &'static [(K, V)],
+}
+
+impl<K, V> Map<K, V> {
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Look up the value stored for `key`.
+    pub fn get<Q>(&self, key: &Q) -> Option<&'static V>
+        where K: Borrow<Q>,
+              Q: Hash + Eq + ?Sized
+    {
+        self.get_entry(key).map(|(_, value)| value)
+    }
+
+    /// Look up the entry stored for `key`.
+    pub fn get_entry<Q>(&self, key: &Q) -> Option<(&'static K, &'static V)>
+        where K: Borrow<Q>,
+              Q: Hash + Eq + ?Sized
+    {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let hashes = hash(key, self.seed);
+        let disp = self.disps[hashes.g as usize % self.disps.len()];
+        let entries: &'static [(K, V)] = self.entries;
+        let entry = &entries[displace(&hashes, disp) as usize % entries.len()];
+
+        if entry.0.borrow() == key {
+            Some((&entry.0, &entry.1))
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the map contains `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: Hash + Eq + ?Sized
+    {
+        self.get_entry(key).is_some()
+    }
+
+    /// Iterate over all entries.
+    pub fn iter(&self) -> Iter<K, V> {
+        Iter(self.entries.iter())
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for Map<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<K, V> IntoIterator for &Map<K, V> {
+    type Item = (&'static K, &'static V);
+    type IntoIter = Iter<K, V>;
+
+    fn into_iter(self) -> Iter<K, V> {
+        self.iter()
+    }
+}
+
+/// Iterator over the entries of a `Map`
+#[derive(Clone, Debug)]
+pub struct Iter<K: 'static, V: 'static>(slice::Iter<'static, (K, V)>);
+
+impl<K, V> Iterator for Iter<K, V> {
+    type Item = (&'static K, &'static V);
+
+    fn next(&mut self) -> Option<(&'static K, &'static V)> {
+        self.0.next().map(|entry| (&entry.0, &entry.1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<K, V> {}
+
+/// FNV-1a hasher, writing integers in little endian byte order
+///
+/// `usize` and `isize` are hashed as 64 bit integers, making hashes
+/// independent of the pointer width as well.
+struct StableHasher(u64);
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        mix(self.0)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.0 = fingerprint::extend(self.0, bytes);
+    }
+
+    fn write_u16(&mut self, i: u16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_u128(&mut self, i: u128) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, i: usize) {
+        self.write_u64(i as u64)
+    }
+
+    fn write_i16(&mut self, i: i16) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_i64(&mut self, i: i64) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_i128(&mut self, i: i128) {
+        self.write(&i.to_le_bytes())
+    }
+
+    fn write_isize(&mut self, i: isize) {
+        self.write_i64(i as i64)
+    }
+}
+
+/// Finalizer of splitmix64, spreading FNV output over all bits
+fn mix(mut x: u64) -> u64 {
+    x ^= x >> 30;
+    x = x.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x ^= x >> 27;
+    x = x.wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Hashes of a key selecting its displacement and slot
+struct Hashes {
+    g: u32,
+    f1: u32,
+    f2: u32,
+}
+
+fn hash<Q: Hash + ?Sized>(key: &Q, seed: u64) -> Hashes {
+    let mut hasher = StableHasher(fingerprint::extend(FNV_OFFSET, &seed.to_le_bytes()));
+    key.hash(&mut hasher);
+    let h = hasher.finish();
+
+    Hashes {
+        g: (h >> 32) as u32,
+        f1: h as u32,
+        f2: mix(h ^ seed) as u32,
+    }
+}
+
+fn displace(hashes: &Hashes, (d1, d2): (u32, u32)) -> u32 {
+    hashes.f1
+        .wrapping_add(d1.wrapping_mul(hashes.f2))
+        .wrapping_add(d2)
+}
+
+/// Hash table layout found for a set of keys
+struct Table {
+    seed: u64,
+    disps: Vec<(u32, u32)>,
+    /// Index of the key stored in each slot
+    slots: Vec<usize>,
+}
+
+impl Table {
+    fn generate<K: Hash>(keys: &[K]) -> Table {
+        (0..)
+            .filter_map(|seed| Table::try_generate(keys, seed))
+            .next()
+            .expect("no perfect hash function found")
+    }
+
+    fn try_generate<K: Hash>(keys: &[K], seed: u64) -> Option<Table> {
+        let hashes: Vec<_> = keys.iter().map(|key| hash(key, seed)).collect();
+        let n = keys.len();
+
+        let mut buckets = vec![Vec::new(); n.div_ceil(LAMBDA)];
+        for (i, hashes) in hashes.iter().enumerate() {
+            let len = buckets.len();
+            buckets[hashes.g as usize % len].push(i);
+        }
+
+        // placing large buckets first, while most slots are still free
+        let mut order: Vec<_> = (0..buckets.len()).collect();
+        order.sort_by_key(|&b| Reverse(buckets[b].len()));
+
+        let mut disps = vec![(0, 0); buckets.len()];
+        let mut slots = vec![None; n];
+        let mut placed = Vec::new();
+
+        'buckets: for b in order {
+            for d1 in 0..n as u32 {
+                for d2 in 0..n as u32 {
+                    placed.clear();
+
+                    for &key in &buckets[b] {
+                        let slot = displace(&hashes[key], (d1, d2)) as usize % n;
+                        if slots[slot].is_some() || placed.iter().any(|&(s, _)| s == slot) {
+                            break;
+                        }
+                        placed.push((slot, key));
+                    }
+
+                    if placed.len() == buckets[b].len() {
+                        for &(slot, key) in &placed {
+                            slots[slot] = Some(key);
+                        }
+                        disps[b] = (d1, d2);
+                        continue 'buckets;
+                    }
+                }
+            }
+
+            return None;
+        }
+
+        Some(Table {
+            seed,
+            disps,
+            slots: slots.into_iter().map(|slot| slot.unwrap()).collect(),
+        })
+    }
+}
+
+/// Render an expression constructing a `Map` of `entries`.
+///
+/// If a key occurs more than once, the last value is kept.
+pub(crate) fn render<K, V, I>(entries: I) -> String
+    where K: ToConstExpr + Hash + Eq,
+          V: ToConstExpr,
+          I: IntoIterator<Item = (K, V)>
+{
+    let entries = dedup_last(entries.into_iter().collect());
+    let keys: Vec<_> = entries.iter().map(|entry| &entry.0).collect();
+    let table = Table::generate(&keys);
+
+    let mut out = ExprWriter::new();
+    render_table(&mut out, &table, &entries).expect("writing to a string cannot fail");
+    out.finish()
+}
+
+/// `entries` without repeated keys, keeping the value added last for each.
+fn dedup_last<K: Hash + Eq, V>(entries: Vec<(K, V)>) -> Vec<(K, V)> {
+    let keep: Vec<bool> = {
+        let last: HashMap<_, _> = entries.iter()
+            .enumerate()
+            .map(|(i, entry)| (&entry.0, i))
+            .collect();
+        entries.iter().enumerate().map(|(i, entry)| last[&entry.0] == i).collect()
+    };
+
+    entries.into_iter()
+        .zip(keep)
+        .filter(|&(_, keep)| keep)
+        .map(|(entry, _)| entry)
+        .collect()
+}
+
+fn render_table<K, V>(out: &mut ExprWriter, table: &Table, entries: &[(K, V)]) -> fmt::Result
+    where K: ToConstExpr,
+          V: ToConstExpr
+{
+    use std::fmt::Write;
+
+    out.write_str("::cconst::Map { seed: ")?;
+    table.seed.to_const_expr(out)?;
+    out.write_str(", disps: &")?;
+    table.disps[..].to_const_expr(out)?;
+    out.write_str(", entries: &[")?;
+    for &slot in &table.slots {
+        let entry = &entries[slot];
+        out.write_str("(")?;
+        entry.0.to_const_expr(out)?;
+        out.write_str(", ")?;
+        entry.1.to_const_expr(out)?;
+        out.write_str("), ")?;
+    }
+    out.write_str("], }")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lay out `entries` like the generated code does, leaking the storage.
+    fn build<K: Hash + Eq, V>(entries: Vec<(K, V)>) -> Map<K, V> {
+        let entries = dedup_last(entries);
+        let table = {
+            let keys: Vec<_> = entries.iter().map(|entry| &entry.0).collect();
+            Table::generate(&keys)
+        };
+
+        let mut entries: Vec<_> = entries.into_iter().map(Some).collect();
+        let entries: Vec<_> = table.slots.iter().map(|&i| entries[i].take().unwrap()).collect();
+
+        Map {
+            seed: table.seed,
+            disps: Box::leak(table.disps.into_boxed_slice()),
+            entries: Box::leak(entries.into_boxed_slice()),
+        }
+    }
+
+    fn assert_own_slots<K: Hash>(keys: &[K]) {
+        let table = Table::generate(keys);
+        let n = keys.len();
+
+        let mut slots = table.slots.clone();
+        slots.sort();
+        assert_eq!(slots, (0..n).collect::<Vec<_>>());
+
+        for (slot, &key) in table.slots.iter().enumerate() {
+            let hashes = hash(&keys[key], table.seed);
+            let disp = table.disps[hashes.g as usize % table.disps.len()];
+            assert_eq!(displace(&hashes, disp) as usize % n, slot);
+        }
+    }
+
+    #[test]
+    fn keys_get_own_slots() {
+        assert_own_slots::<u32>(&[]);
+        assert_own_slots(&[42u32]);
+        assert_own_slots(&(0..1000u32).collect::<Vec<_>>());
+        assert_own_slots(&(0..1000).map(|i| format!("key{}", i)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_table() {
+        let table = Table::generate::<u32>(&[]);
+        assert!(table.disps.is_empty());
+        assert!(table.slots.is_empty());
+
+        let map = build::<u32, u32>(Vec::new());
+        assert!(map.is_empty());
+        assert_eq!(map.get(&0), None);
+    }
+
+    #[test]
+    fn single_key() {
+        let map = build(vec![(7u32, "seven")]);
+        assert_eq!(map.get_entry(&7), Some((&7, &"seven")));
+        assert_eq!(map.get(&8), None);
+    }
+
+    #[test]
+    fn get_entry_hits_and_misses() {
+        let map = build((0..1000u32).map(|i| (format!("key{}", i), i * 2)).collect());
+        assert_eq!(map.len(), 1000);
+
+        for i in 0..1000 {
+            let key = format!("key{}", i);
+            assert_eq!(map.get_entry(&key[..]), Some((&key, &(i * 2))));
+        }
+        for i in 1000..2000 {
+            assert!(!map.contains_key(&format!("key{}", i)[..]));
+        }
+        assert_eq!(map.get(""), None);
+    }
+
+    #[test]
+    fn repeated_key_keeps_last_value() {
+        let map = build(vec![(1u32, 10u32), (2, 20), (1, 30), (3, 40), (2, 50)]);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&1), Some(&30));
+        assert_eq!(map.get(&2), Some(&50));
+        assert_eq!(map.get(&3), Some(&40));
+
+        assert_eq!(dedup_last(vec![(1, 'a'), (2, 'b'), (1, 'c')]), vec![(2, 'b'), (1, 'c')]);
+    }
+
+    #[test]
+    fn pointer_sized_integers_hash_as_64_bit() {
+        let a = hash(&0x1234_5678usize, 3);
+        let b = hash(&0x1234_5678u64, 3);
+        assert_eq!((a.g, a.f1, a.f2), (b.g, b.f1, b.f2));
+
+        let a = hash(&-5isize, 3);
+        let b = hash(&-5i64, 3);
+        assert_eq!((a.g, a.f1, a.f2), (b.g, b.f1, b.f2));
+    }
+}
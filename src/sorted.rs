@@ -0,0 +1,275 @@
+//! Sorted sets and lookup tables
+//!
+//! Keys are sorted and deduplicated by the build script, lookups at runtime
+//! use binary search. Unlike `Map`, iteration is in key order and ranges of
+//! keys can be queried.
+
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::{Bound, Range, RangeBounds};
+use std::slice;
+
+use expr::{ExprWriter, ToConstExpr};
+
+/// Immutable set generated by `CopyConsts::add_set`
+pub struct Set<K: 'static> {
+    #[doc(hidden)]
+    pub keys: &'static [K],
+}
+
+impl<K> Set<K> {
+    /// Number of keys
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Returns `true` if the set has no keys.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Returns `true` if the set contains `key`.
+    pub fn contains<Q>(&self, key: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: Ord + ?Sized
+    {
+        self.keys.binary_search_by(|k| k.borrow().cmp(key)).is_ok()
+    }
+
+    /// Keys within `range`, in ascending order.
+    pub fn range<Q, R>(&self, range: R) -> &'static [K]
+        where K: Borrow<Q>,
+              Q: Ord + ?Sized,
+              R: RangeBounds<Q>
+    {
+        let keys: &'static [K] = self.keys;
+        &keys[bounds(keys, |k| k.borrow(), &range)]
+    }
+
+    /// All keys, in ascending order.
+    pub fn as_slice(&self) -> &'static [K] {
+        self.keys
+    }
+
+    /// Iterate over all keys, in ascending order.
+    pub fn iter(&self) -> slice::Iter<'static, K> {
+        self.keys.iter()
+    }
+}
+
+impl<K: fmt::Debug> fmt::Debug for Set<K> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+impl<K> IntoIterator for &Set<K> {
+    type Item = &'static K;
+    type IntoIter = slice::Iter<'static, K>;
+
+    fn into_iter(self) -> slice::Iter<'static, K> {
+        self.iter()
+    }
+}
+
+/// Immutable lookup table generated by `CopyConsts::add_sorted_table`
+pub struct SortedTable<K: 'static, V: 'static> {
+    #[doc(hidden)]
+    pub entries: &'static [(K, V)],
+}
+
+impl<K, V> SortedTable<K, V> {
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the table has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Look up the value stored for `key`.
+    pub fn get<Q>(&self, key: &Q) -> Option<&'static V>
+        where K: Borrow<Q>,
+              Q: Ord + ?Sized
+    {
+        let entries: &'static [(K, V)] = self.entries;
+        entries.binary_search_by(|entry| entry.0.borrow().cmp(key))
+            .ok()
+            .map(|i| &entries[i].1)
+    }
+
+    /// Returns `true` if the table contains `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+        where K: Borrow<Q>,
+              Q: Ord + ?Sized
+    {
+        self.get(key).is_some()
+    }
+
+    /// Entries with keys within `range`, in ascending order.
+    pub fn range<Q, R>(&self, range: R) -> &'static [(K, V)]
+        where K: Borrow<Q>,
+              Q: Ord + ?Sized,
+              R: RangeBounds<Q>
+    {
+        let entries: &'static [(K, V)] = self.entries;
+        &entries[bounds(entries, |entry| entry.0.borrow(), &range)]
+    }
+
+    /// All entries, in ascending order of keys.
+    pub fn as_slice(&self) -> &'static [(K, V)] {
+        self.entries
+    }
+
+    /// Iterate over all entries, in ascending order of keys.
+    pub fn iter(&self) -> slice::Iter<'static, (K, V)> {
+        self.entries.iter()
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for SortedTable<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.iter().map(|entry| (&entry.0, &entry.1)))
+            .finish()
+    }
+}
+
+impl<K, V> IntoIterator for &SortedTable<K, V> {
+    type Item = &'static (K, V);
+    type IntoIter = slice::Iter<'static, (K, V)>;
+
+    fn into_iter(self) -> slice::Iter<'static, (K, V)> {
+        self.iter()
+    }
+}
+
+/// Indices of the elements of sorted `items` with keys within `range`.
+fn bounds<T, Q, F, R>(items: &[T], key: F, range: &R) -> Range<usize>
+    where Q: Ord + ?Sized,
+          F: Fn(&T) -> &Q,
+          R: RangeBounds<Q>
+{
+    let start = match range.start_bound() {
+        Bound::Included(start) => items.partition_point(|item| key(item) < start),
+        Bound::Excluded(start) => items.partition_point(|item| key(item) <= start),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) => items.partition_point(|item| key(item) <= end),
+        Bound::Excluded(end) => items.partition_point(|item| key(item) < end),
+        Bound::Unbounded => items.len(),
+    };
+
+    // empty for inverted ranges, instead of panicking like slicing would
+    start..end.max(start)
+}
+
+/// Render an expression constructing a `Set` of `keys`.
+pub(crate) fn render_set<K, I>(keys: I) -> String
+    where K: ToConstExpr + Ord,
+          I: IntoIterator<Item = K>
+{
+    let mut keys: Vec<_> = keys.into_iter().collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut out = ExprWriter::new();
+    write_literal(&mut out, "::cconst::Set { keys: &[", &keys)
+        .expect("writing to a string cannot fail");
+    out.finish()
+}
+
+/// Render an expression constructing a `SortedTable` of `entries`.
+///
+/// If a key occurs more than once, the last value is kept.
+pub(crate) fn render_table<K, V, I>(entries: I) -> String
+    where K: ToConstExpr + Ord,
+          V: ToConstExpr,
+          I: IntoIterator<Item = (K, V)>
+{
+    let entries = sort_entries(entries.into_iter().collect());
+
+    let mut out = ExprWriter::new();
+    write_literal(&mut out, "::cconst::SortedTable { entries: &[", &entries)
+        .expect("writing to a string cannot fail");
+    out.finish()
+}
+
+/// `entries` sorted by key, keeping the value added last for repeated keys.
+fn sort_entries<K: Ord, V>(mut entries: Vec<(K, V)>) -> Vec<(K, V)> {
+    // stable, so equal keys remain in the order they were added in
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+    // `dedup_by` keeps the first of equal entries, reversing puts the last
+    // added one first
+    entries.reverse();
+    entries.dedup_by(|a, b| a.0 == b.0);
+    entries.reverse();
+    entries
+}
+
+fn write_literal<T: ToConstExpr>(out: &mut ExprWriter, open: &str, items: &[T]) -> fmt::Result {
+    use std::fmt::Write;
+
+    out.write_str(open)?;
+    out.write_list(items)?;
+    out.write_str("], }")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Bound::{Excluded, Included, Unbounded};
+
+    const KEYS: &[u32] = &[1, 3, 5, 7];
+
+    fn keys<R: RangeBounds<u32>>(range: R) -> &'static [u32] {
+        &KEYS[bounds(KEYS, |k| k, &range)]
+    }
+
+    #[test]
+    fn bounds_of_ranges() {
+        assert_eq!(keys(..), KEYS);
+        assert_eq!(keys(3..7), &[3, 5]);
+        assert_eq!(keys(3..=7), &[3, 5, 7]);
+        assert_eq!(keys(2..6), &[3, 5]);
+        assert_eq!(keys(..5), &[1, 3]);
+        assert_eq!(keys(..=5), &[1, 3, 5]);
+        assert_eq!(keys(5..), &[5, 7]);
+        assert_eq!(keys((Excluded(3), Included(7))), &[5, 7]);
+        assert_eq!(keys((Excluded(3), Excluded(7))), &[5]);
+        assert_eq!(keys((Excluded(0), Unbounded)), KEYS);
+        assert_eq!(keys(8..), &[] as &[u32]);
+        assert_eq!(keys(4..5), &[] as &[u32]);
+    }
+
+    #[test]
+    fn inverted_ranges_are_empty() {
+        assert_eq!(bounds(KEYS, |k| k, &(Included(7), Excluded(3))), 3..3);
+        assert_eq!(keys((Excluded(5), Excluded(5))), &[] as &[u32]);
+        assert_eq!(keys((Included(7), Included(1))), &[] as &[u32]);
+    }
+
+    #[test]
+    fn set_and_table_lookups() {
+        let set = Set { keys: &["a", "c", "e"] };
+        assert!(set.contains("c"));
+        assert!(!set.contains("d"));
+        assert_eq!(set.range("b".."e"), &["c"]);
+
+        let table = SortedTable { entries: &[(1, 'a'), (4, 'b'), (9, 'c')] };
+        assert_eq!(table.get(&4), Some(&'b'));
+        assert_eq!(table.get(&5), None);
+        assert_eq!(table.range(2..), &[(4, 'b'), (9, 'c')]);
+    }
+
+    #[test]
+    fn repeated_key_keeps_last_value() {
+        assert_eq!(sort_entries(vec![(3, 'a'), (1, 'b'), (3, 'c'), (2, 'd'), (1, 'e'), (3, 'f')]),
+                   vec![(1, 'e'), (2, 'd'), (3, 'f')]);
+        assert_eq!(sort_entries::<u8, u8>(Vec::new()), vec![]);
+    }
+}
@@ -0,0 +1,151 @@
+//! Lookup tables indexed by integers
+//!
+//! Tables added through `CopyConsts::add_table` store one value for each
+//! integer of a range, starting at an arbitrary index.
+
+use std::convert::TryFrom;
+use std::ops::Index;
+use std::{fmt, slice};
+
+/// Integer types usable as the index of a `Table`
+pub trait TableIndex: Copy {
+    /// Position of `self` in a table starting at `start`, if not below it
+    fn offset_from(self, start: Self) -> Option<usize>;
+}
+
+macro_rules! impl_table_index {
+    ($($ctype:ident),*) => ($(
+        impl TableIndex for $ctype {
+            fn offset_from(self, start: $ctype) -> Option<usize> {
+                if self < start {
+                    return None;
+                }
+
+                // widened first, the difference of signed integers may not
+                // fit their own type
+                usize::try_from(self as i128 - start as i128).ok()
+            }
+        }
+    )*)
+}
+
+impl_table_index!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// Immutable table generated by `CopyConsts::add_table`
+///
+/// Holds `len()` values, for consecutive indices beginning at `start()`.
+pub struct Table<I: 'static, T: 'static> {
+    #[doc(hidden)]
+    pub start: I,
+    #[doc(hidden)]
+    pub values: &'static [T],
+}
+
+impl<I: TableIndex, T> Table<I, T> {
+    /// First index of the table
+    pub fn start(&self) -> I {
+        self.start
+    }
+
+    /// Number of values
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns `true` if the table has no values.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Look up the value stored for `index`.
+    pub fn get(&self, index: I) -> Option<&'static T> {
+        let values: &'static [T] = self.values;
+        index.offset_from(self.start).and_then(|offset| values.get(offset))
+    }
+
+    /// All values, in order of their indices.
+    pub fn as_slice(&self) -> &'static [T] {
+        self.values
+    }
+
+    /// Iterate over all values, in order of their indices.
+    pub fn iter(&self) -> slice::Iter<'static, T> {
+        self.values.iter()
+    }
+}
+
+impl<I: fmt::Debug, T: fmt::Debug> fmt::Debug for Table<I, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Table")
+            .field("start", &self.start)
+            .field("values", &self.values)
+            .finish()
+    }
+}
+
+impl<I: TableIndex, T> Index<I> for Table<I, T> {
+    type Output = T;
+
+    fn index(&self, index: I) -> &T {
+        self.get(index).expect("table index out of bounds")
+    }
+}
+
+impl<I: TableIndex, T> IntoIterator for &Table<I, T> {
+    type Item = &'static T;
+    type IntoIter = slice::Iter<'static, T>;
+
+    fn into_iter(self) -> slice::Iter<'static, T> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offsets_from_signed_start() {
+        assert_eq!((-5i8).offset_from(-5), Some(0));
+        assert_eq!(0i8.offset_from(-5), Some(5));
+        assert_eq!(4i8.offset_from(-5), Some(9));
+        assert_eq!((-6i8).offset_from(-5), None);
+        assert_eq!(i8::MAX.offset_from(i8::MIN), Some(255));
+        assert_eq!(i64::MAX.offset_from(i64::MIN), usize::try_from(u64::MAX).ok());
+    }
+
+    #[test]
+    fn offsets_at_unsigned_extremes() {
+        assert_eq!(0u64.offset_from(1), None);
+        assert_eq!(u64::MAX.offset_from(u64::MAX), Some(0));
+        assert_eq!(u64::MAX.offset_from(u64::MAX - 1), Some(1));
+        assert_eq!(u64::MAX.offset_from(0), usize::try_from(u64::MAX).ok());
+        assert_eq!(u8::MAX.offset_from(0), Some(255));
+    }
+
+    #[test]
+    fn lookups() {
+        let table = Table {
+            start: -5i8,
+            values: &[10, 11, 12, 13, 14, 15, 16, 17, 18, 19],
+        };
+
+        assert_eq!(table.get(-5), Some(&10));
+        assert_eq!(table.get(0), Some(&15));
+        assert_eq!(table[4], 19);
+        assert_eq!(table.get(-6), None);
+        assert_eq!(table.get(5), None);
+        assert_eq!(table.get(i8::MAX), None);
+        assert_eq!(table.get(i8::MIN), None);
+
+        let empty = Table::<u64, u8> { start: u64::MAX, values: &[] };
+        assert_eq!(empty.get(u64::MAX), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "table index out of bounds")]
+    fn index_out_of_range() {
+        let table = Table { start: 1u32, values: &[1u8] };
+        let _ = table[0];
+    }
+}
@@ -0,0 +1,176 @@
+//! Compilation target information
+//!
+//! Byte images are taken from the memory of the build script, which runs on
+//! the host. When cross-compiling, the target may use a different byte order
+//! or pointer width. Cargo passes the target's properties to build scripts
+//! through `CARGO_CFG_TARGET_*` environment variables.
+
+use std::env;
+
+use layout::{FieldKind, Layout};
+
+/// Byte order and pointer width of a platform
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Target {
+    triple: String,
+    big_endian: bool,
+    pointer_width: usize,
+}
+
+impl Target {
+    /// The platform the build script is running on.
+    fn host() -> Target {
+        Target {
+            triple: "host".to_owned(),
+            big_endian: cfg!(target_endian = "big"),
+            pointer_width: usize::BITS as usize,
+        }
+    }
+
+    /// The platform the crate is compiled for.
+    ///
+    /// Falls back to the host if not run by cargo.
+    pub(crate) fn from_env() -> Target {
+        let host = Target::host();
+
+        Target {
+            triple: env::var("TARGET").unwrap_or(host.triple),
+            big_endian: match env::var("CARGO_CFG_TARGET_ENDIAN") {
+                Ok(endian) => endian == "big",
+                Err(_) => host.big_endian,
+            },
+            pointer_width: env::var("CARGO_CFG_TARGET_POINTER_WIDTH")
+                .ok()
+                .and_then(|width| width.parse().ok())
+                .unwrap_or(host.pointer_width),
+        }
+    }
+
+    pub(crate) fn triple(&self) -> &str {
+        &self.triple
+    }
+
+    /// Converts a byte image taken on the host into its target representation.
+    ///
+    /// `image` consists of one or more values described by `layout`, stored
+    /// back to back. Scalars are byte-swapped if the byte order differs.
+    /// Fails if the representation cannot be converted, because of
+    /// pointer-sized scalars on a target with a different pointer width or
+    /// multi-byte opaque data on a target with a different byte order.
+    pub(crate) fn convert(&self, image: &[u8], layout: &Layout) -> Result<Vec<u8>, String> {
+        let host = Target::host();
+        let swap = self.big_endian != host.big_endian;
+
+        for field in layout.fields() {
+            if field.kind == FieldKind::PointerSized && self.pointer_width != host.pointer_width {
+                return Err(format!("pointer-sized field at offset {} cannot be stored for a \
+                                    {}-bit target",
+                                   field.offset,
+                                   self.pointer_width));
+            }
+
+            if swap && field.kind == FieldKind::Opaque && field.size > 1 {
+                return Err(format!("opaque field at offset {} cannot be converted to the \
+                                    target's byte order",
+                                   field.offset));
+            }
+        }
+
+        let mut converted = image.to_vec();
+
+        if swap && layout.size() > 0 {
+            for value in converted.chunks_mut(layout.size()) {
+                for field in layout.fields() {
+                    value[field.offset..field.offset + field.size].reverse();
+                }
+            }
+        }
+
+        Ok(converted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    /// Target differing from the host in byte order only
+    fn swapped() -> Target {
+        let host = Target::host();
+
+        Target {
+            triple: "swapped".to_owned(),
+            big_endian: !host.big_endian,
+            ..host
+        }
+    }
+
+    /// Target differing from the host in pointer width only
+    fn narrow() -> Target {
+        let host = Target::host();
+
+        Target {
+            triple: "narrow".to_owned(),
+            pointer_width: if host.pointer_width == 32 { 64 } else { 32 },
+            ..host
+        }
+    }
+
+    #[test]
+    fn host_keeps_image() {
+        let mut layout = Layout::new(4 + size_of::<usize>());
+        layout.field(0, 4).typed_field(4, size_of::<usize>(), FieldKind::PointerSized);
+        let image: Vec<u8> = (0..layout.size() as u8).collect();
+
+        assert_eq!(Target::host().convert(&image, &layout), Ok(image));
+    }
+
+    #[test]
+    fn swaps_each_scalar_field() {
+        let mut layout = Layout::new(8);
+        layout.typed_field(0, 2, FieldKind::Scalar)
+            .typed_field(2, 1, FieldKind::Scalar)
+            .typed_field(4, 4, FieldKind::Scalar);
+
+        // the padding byte at offset 3 is left alone
+        assert_eq!(swapped().convert(&[1, 2, 3, 0xff, 4, 5, 6, 7], &layout),
+                   Ok(vec![2, 1, 3, 0xff, 7, 6, 5, 4]));
+    }
+
+    #[test]
+    fn swaps_each_element_of_slice() {
+        let image = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+
+        assert_eq!(swapped().convert(&image, &Layout::scalar(4)),
+                   Ok(vec![4, 3, 2, 1, 8, 7, 6, 5, 12, 11, 10, 9]));
+        assert_eq!(swapped().convert(&[], &Layout::new(0)), Ok(vec![]));
+    }
+
+    #[test]
+    fn refuses_pointer_sized_fields_of_other_width() {
+        let mut layout = Layout::new(2 * size_of::<usize>());
+        layout.typed_field(size_of::<usize>(), size_of::<usize>(), FieldKind::PointerSized);
+
+        let err = narrow().convert(&vec![0; layout.size()], &layout).unwrap_err();
+        assert!(err.contains(&format!("offset {}", size_of::<usize>())), "{}", err);
+
+        // same width, only swapped
+        let image: Vec<u8> = (0..layout.size() as u8).collect();
+        let mut expected = image.clone();
+        expected[size_of::<usize>()..].reverse();
+        assert_eq!(swapped().convert(&image, &layout), Ok(expected));
+    }
+
+    #[test]
+    fn refuses_opaque_fields_of_other_byte_order() {
+        let mut layout = Layout::new(4);
+        layout.field(0, 1).field(2, 2);
+
+        let err = swapped().convert(&[1, 2, 3, 4], &layout).unwrap_err();
+        assert!(err.contains("offset 2"), "{}", err);
+
+        assert_eq!(narrow().convert(&[1, 2, 3, 4], &layout), Ok(vec![1, 2, 3, 4]));
+        assert_eq!(swapped().convert(&[1, 2], &Layout::dense(1)), Ok(vec![1, 2]));
+    }
+}
@@ -0,0 +1,151 @@
+//! Naming types in generated code
+//!
+//! `std::any::type_name` returns the path of a type inside the crate defining
+//! it, which for standard library types is often a private module of `core` or
+//! `alloc`. These paths are rewritten into fully qualified paths that can be
+//! used at the `include!` site.
+
+use std::any::type_name;
+use std::{error, fmt};
+
+/// Private standard library modules and their public re-exports
+const STD_ALIASES: &[(&str, &str)] = &[("core::net::ip_addr::", "::std::net::"),
+                                       ("core::net::socket_addr::", "::std::net::"),
+                                       ("core::num::nonzero::", "::std::num::"),
+                                       ("core::num::saturating::", "::std::num::"),
+                                       ("core::num::wrapping::", "::std::num::"),
+                                       ("core::ops::range::", "::std::ops::"),
+                                       ("core::ffi::c_str::", "::std::ffi::"),
+                                       ("core::sync::atomic::", "::std::sync::atomic::"),
+                                       ("std::collections::hash::map::", "::std::collections::"),
+                                       ("std::collections::hash::set::", "::std::collections::")];
+
+/// Error for types that cannot be named at the `include!` site
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnresolvedType {
+    type_name: String,
+    reason: &'static str,
+}
+
+impl UnresolvedType {
+    fn new(type_name: &str, reason: &'static str) -> UnresolvedType {
+        UnresolvedType {
+            type_name: type_name.to_owned(),
+            reason,
+        }
+    }
+
+    /// Name of the type as returned by `std::any::type_name`
+    pub fn type_name(&self) -> &str {
+        &self.type_name
+    }
+}
+
+impl fmt::Display for UnresolvedType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot name type `{}`: {}", self.type_name, self.reason)
+    }
+}
+
+impl error::Error for UnresolvedType {}
+
+/// Returns a fully qualified path for `T`.
+pub(crate) fn type_path<T: ?Sized>() -> Result<String, UnresolvedType> {
+    resolve(type_name::<T>())
+}
+
+fn resolve(type_name: &str) -> Result<String, UnresolvedType> {
+    if type_name.contains("{{") {
+        return Err(UnresolvedType::new(type_name, "anonymous types cannot be named"));
+    }
+
+    let mut path = String::new();
+    let mut rest = type_name;
+
+    while let Some(c) = rest.chars().next() {
+        if c.is_alphabetic() || c == '_' {
+            let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+                .unwrap_or(rest.len());
+            path += &resolve_segments(type_name, &rest[..len])?;
+            rest = &rest[len..];
+        } else {
+            // lifetimes are omitted by `type_name`, references in generated
+            // code always point to static data
+            path += if c == '&' { "&'static " } else { &rest[..c.len_utf8()] };
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+
+    Ok(path)
+}
+
+/// Resolves a single `a::b::C` path, without generic arguments.
+fn resolve_segments(type_name: &str, segments: &str) -> Result<String, UnresolvedType> {
+    let krate = match segments.find("::") {
+        Some(pos) => &segments[..pos],
+        // primitives and keywords such as `mut` or `dyn`
+        None => return Ok(segments.to_owned()),
+    };
+
+    if krate.starts_with("build_script_") {
+        return Err(UnresolvedType::new(type_name, "type is defined in the build script"));
+    }
+
+    if krate != "core" && krate != "alloc" && krate != "std" {
+        return Ok(format!("::{}", segments));
+    }
+
+    for &(private, public) in STD_ALIASES {
+        if let Some(name) = segments.strip_prefix(private) {
+            return Ok(format!("{}{}", public, name));
+        }
+    }
+
+    // types directly inside a public module of `core` or `alloc` are
+    // re-exported by `std` under the same name
+    if segments.matches("::").count() == 2 {
+        Ok(format!("::std{}", &segments[krate.len()..]))
+    } else {
+        Err(UnresolvedType::new(type_name, "unknown standard library re-export"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_private_std_modules() {
+        assert_eq!(resolve("core::net::ip_addr::Ipv4Addr"),
+                   Ok("::std::net::Ipv4Addr".to_owned()));
+        assert_eq!(resolve("core::option::Option<core::num::nonzero::NonZero<u8>>"),
+                   Ok("::std::option::Option<::std::num::NonZero<u8>>".to_owned()));
+    }
+
+    #[test]
+    fn falls_back_to_std_for_two_segments() {
+        assert_eq!(resolve("alloc::string::String"), Ok("::std::string::String".to_owned()));
+
+        let err = resolve("core::iter::adapters::map::Map<u8>").unwrap_err();
+        assert_eq!(err.type_name(), "core::iter::adapters::map::Map<u8>");
+    }
+
+    #[test]
+    fn keeps_primitives_and_other_crates() {
+        assert_eq!(resolve("&str"), Ok("&'static str".to_owned()));
+        assert_eq!(resolve("[u32; 3]"), Ok("[u32; 3]".to_owned()));
+        assert_eq!(resolve("(u8, &[i16])"), Ok("(u8, &'static [i16])".to_owned()));
+        assert_eq!(resolve("mycrate::a::B<u8>"), Ok("::mycrate::a::B<u8>".to_owned()));
+        assert_eq!(type_path::<Option<&str>>(),
+                   Ok("::std::option::Option<&'static str>".to_owned()));
+    }
+
+    #[test]
+    fn refuses_unnameable_types() {
+        let err = resolve("build_script_build::X").unwrap_err();
+        assert_eq!(err.type_name(), "build_script_build::X");
+
+        assert!(resolve("{{closure}}").is_err());
+        assert!(resolve("mycrate::main::{{closure}}").is_err());
+    }
+}